@@ -0,0 +1,322 @@
+//! Frame by frame animation from sprite sheets. A [SpriteSheet] says where
+//! each frame is in an image and which frames make up each animation, an
+//! [Animator] plays them and picks the animation from what the entity's
+//! physics is doing, see `assets/sprites/player.ron` for an example
+//!
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use super::Game;
+use super::physics::{Physics, State};
+
+/// What happens when an animation gets to its last frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoopMode {
+    // Start again from the first frame
+    Loop,
+    // Stay on the last frame
+    Once,
+    // Play backwards to the first frame then forwards again
+    PingPong,
+}
+
+/// A run of frames from a [SpriteSheet]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    // Index into the sheet's frames and how many seconds to show it for
+    pub frames: Vec<(usize, f32)>,
+    pub mode: LoopMode,
+}
+
+/// An image split up into frames and the animations made from them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpriteSheet {
+    // Path of the PNG the frames are in
+    pub texture: String,
+    // Width and height of the PNG in pixels
+    pub size: (u32, u32),
+    // Where each frame is in pixels from the top left, as x, y, width and
+    // height
+    pub frames: Vec<[u32; 4]>,
+    // Sorted so errors always come up in the same order
+    pub animations: BTreeMap<String, Animation>,
+    // Where we loaded the sheet from, so levels can be saved with it
+    #[serde(skip)]
+    pub path: String,
+}
+
+/// Sheets built into the game so the default level works wherever we are
+/// run from, these are used instead of the file at the same path
+const BUILT_IN: [(&str, &str); 1] = [(
+    "assets/sprites/player.ron",
+    include_str!("../../assets/sprites/player.ron"),
+)];
+
+impl SpriteSheet {
+    /// Read and check a sprite sheet from a RON file, or from the game if
+    /// it is one of the [BUILT_IN] sheets
+    pub fn load(path: &str) -> Result<Self, String> {
+        match BUILT_IN.iter().find(|(built_in, _)| *built_in == path) {
+            Some((_, text)) => Self::parse(text, path),
+            None => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())?;
+                Self::parse(&text, path)
+            },
+        }
+    }
+    /// Parse and check a sprite sheet from RON, `path` is where it is saved
+    pub fn parse(text: &str, path: &str) -> Result<Self, String> {
+        let mut sheet: SpriteSheet = ron::from_str(text)
+            .map_err(|e| e.to_string())?;
+        sheet.validate()?;
+        sheet.path = path.to_string();
+        Ok(sheet)
+    }
+    /// Check every frame is inside the image and every animation can play
+    fn validate(&self) -> Result<(), String> {
+        let (width, height) = self.size;
+        if width == 0 || height == 0 {
+            return Err("sheet size must be more than 0".into())
+        }
+        for (index, &[x, y, w, h]) in self.frames.iter().enumerate() {
+            if w == 0 || h == 0 || x + w > width || y + h > height {
+                return Err(format!(
+                    "frame {index} {:?} is not inside the {width}x{height} \
+                    image", [x, y, w, h]
+                ))
+            }
+        }
+        if self.animations.is_empty() {
+            return Err("sheet needs at least one animation".into())
+        }
+        for (name, animation) in &self.animations {
+            if animation.frames.is_empty() {
+                return Err(format!("animation {name:?} has no frames"))
+            }
+            for &(frame, duration) in &animation.frames {
+                if frame >= self.frames.len() {
+                    return Err(format!(
+                        "animation {name:?} uses frame {frame} but there \
+                        are only {}", self.frames.len()
+                    ))
+                }
+                if !(duration.is_finite() && duration > 0.0) {
+                    return Err(format!(
+                        "animation {name:?} frame durations must be more \
+                        than 0.0, one is {duration}"
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+    /// The part of the image `frame` is in, from 0.0 to 1.0 as a
+    /// [super::entity::Sprite] region
+    fn region(&self, frame: usize) -> [f32; 4] {
+        let [x, y, w, h] = self.frames[frame];
+        let (width, height) = (self.size.0 as f32, self.size.1 as f32);
+        [
+            x as f32 / width,
+            y as f32 / height,
+            (x + w) as f32 / width,
+            (y + h) as f32 / height,
+        ]
+    }
+}
+
+/// Plays animations from a [SpriteSheet] on an entity's sprite
+#[derive(Debug, Clone)]
+pub struct Animator {
+    // Shared by every entity using the same sheet
+    pub sheet: Rc<SpriteSheet>,
+    // The name of the animation playing
+    playing: String,
+    // Where we are in the animation's frames
+    frame: usize,
+    // How long we have been on this frame in seconds
+    time: f32,
+    // Going backwards through a [LoopMode::PingPong] animation
+    reverse: bool,
+    // Draw the frames mirrored, our sheets face right so this faces left
+    pub flip: bool,
+}
+
+impl Animator {
+    /// Start on the sheet's "idle" animation, or its first if it has none
+    pub fn new(sheet: Rc<SpriteSheet>) -> Self {
+        let playing = match sheet.animations.contains_key("idle") {
+            true => "idle".to_string(),
+            false => sheet.animations.keys().next()
+                .expect("Sprite sheets always have an animation")
+                .clone(),
+        };
+        Self {
+            sheet,
+            playing,
+            frame: 0,
+            time: 0.0,
+            reverse: false,
+            flip: false,
+        }
+    }
+    /// Switch to the animation called `name` from its first frame, nothing
+    /// happens if it is already playing or the sheet does not have it
+    pub fn play(&mut self, name: &str) {
+        if self.playing == name || !self.sheet.animations.contains_key(name) {
+            return
+        }
+        self.playing = name.to_string();
+        self.frame = 0;
+        self.time = 0.0;
+        self.reverse = false;
+    }
+    /// Move the animation on by `dt` seconds
+    pub fn tick(&mut self, dt: f32) {
+        let sheet = Rc::clone(&self.sheet);
+        let animation = &sheet.animations[&self.playing];
+        self.time += dt;
+        loop {
+            let duration = animation.frames[self.frame].1;
+            if self.time < duration { break }
+            if !self.next_frame(animation) {
+                // Hold the last frame of a [LoopMode::Once] animation
+                self.time = duration;
+                break
+            }
+            self.time -= duration;
+        }
+    }
+    /// Go to the frame after this one, false if the animation has stopped
+    fn next_frame(&mut self, animation: &Animation) -> bool {
+        let last = animation.frames.len() - 1;
+        match animation.mode {
+            LoopMode::Loop => {
+                self.frame = if self.frame == last { 0 } else { self.frame + 1 };
+            },
+            LoopMode::Once => {
+                if self.frame == last { return false }
+                self.frame += 1;
+            },
+            LoopMode::PingPong => {
+                if last == 0 { return true }
+                if self.frame == 0 { self.reverse = false }
+                if self.frame == last { self.reverse = true }
+                match self.reverse {
+                    true => self.frame -= 1,
+                    false => self.frame += 1,
+                }
+            },
+        }
+        true
+    }
+    /// The part of the sheet to draw right now as a sprite region
+    pub fn region(&self) -> [f32; 4] {
+        let (frame, _) = self.sheet.animations[&self.playing].frames[self.frame];
+        let [left, top, right, bottom] = self.sheet.region(frame);
+        match self.flip {
+            true => [right, top, left, bottom],
+            false => [left, top, right, bottom],
+        }
+    }
+}
+
+/// The animation to play for what the physics is doing, standing still is
+/// "idle" and walking is "run"
+fn animation_for(physics: &Physics) -> &'static str {
+    match physics.state {
+        State::None if physics.body.velocity.x != 0.0 => "run",
+        State::None | State::Static => "idle",
+        State::Jumping => "jump",
+        State::Falling => "fall",
+    }
+}
+
+/// Pick each animator's animation from its physics, move it on a tick and
+/// show the frame on its sprite
+pub fn update(game: &mut Game) {
+    let dt = game.dt;
+    let world = &mut game.world;
+    for (id, animator, physics) in world.animators.join_mut(&mut world.bodies) {
+        // Face the way we are moving, or the way we last moved
+        let vx = physics.body.velocity.x;
+        if vx != 0.0 {
+            animator.flip = vx < 0.0;
+        }
+        animator.play(animation_for(physics));
+        animator.tick(dt);
+
+        if let Some(sprite) = world.sprites.get_mut(id) {
+            sprite.region = animator.region();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An animator playing three frames, each 1.0 seconds long, in `mode`
+    fn animator(mode: &str) -> Animator {
+        let sheet = SpriteSheet::parse(&format!("(
+            texture: \"test.png\",
+            size: (30, 10),
+            frames: [(0, 0, 10, 10), (10, 0, 10, 10), (20, 0, 10, 10)],
+            animations: {{
+                \"idle\": (
+                    frames: [(0, 1.0), (1, 1.0), (2, 1.0)],
+                    mode: {mode},
+                ),
+            }},
+        )"), "test.ron").unwrap();
+        Animator::new(Rc::new(sheet))
+    }
+
+    /// The frame shown after each tick of 1.0 seconds
+    fn frames(mut animator: Animator, ticks: usize) -> Vec<usize> {
+        (0..ticks).map(|_| {
+            animator.tick(1.0);
+            animator.frame
+        }).collect()
+    }
+
+    #[test]
+    fn loop_starts_again() {
+        assert_eq!(frames(animator("Loop"), 5), [1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn once_stays_on_the_last_frame() {
+        assert_eq!(frames(animator("Once"), 5), [1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn ping_pong_goes_back_and_forth() {
+        assert_eq!(frames(animator("PingPong"), 6), [1, 2, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn long_ticks_skip_frames() {
+        let mut animator = animator("Loop");
+        animator.tick(4.5);
+        assert_eq!(animator.frame, 1);
+        // Flipped frames swap left and right
+        animator.flip = true;
+        assert_eq!(animator.region(), [2.0 / 3.0, 0.0, 1.0 / 3.0, 1.0]);
+    }
+
+    #[test]
+    fn bad_sheets_are_rejected() {
+        for (frames, animation) in [
+            ("(25, 0, 10, 10)", "[(0, 1.0)]"),
+            ("(0, 0, 10, 10)", "[(1, 1.0)]"),
+            ("(0, 0, 10, 10)", "[(0, 0.0)]"),
+            ("(0, 0, 10, 10)", "[]"),
+        ] {
+            let text = format!("(texture: \"test.png\", size: (30, 10), \
+                frames: [{frames}], \
+                animations: {{\"idle\": (frames: {animation}, mode: Loop)}})");
+            assert!(SpriteSheet::parse(&text, "test.ron").is_err(), "{text}");
+        }
+    }
+}
@@ -1,18 +1,166 @@
-//! Our camera will move the world and not the player
-//! 
-use crate::engine::entity::Transform2D;
-
+//! Our camera decides which part of the world ends up on screen, it can
+//! follow an entity so levels can be bigger than one screen
+//!
 use super::Game;
-use super::entity::Entity;
+use super::commands::WorldEvent;
+use super::entity::Point;
+use super::world::EntityId;
+use crate::globals::{CAMERA_DEAD_ZONE, CAMERA_SMOOTHING};
+
+/// How we fit what the camera sees onto a screen of a different shape to
+/// [crate::globals::VIEW_WIDTH] by [crate::globals::VIEW_HEIGHT]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaling {
+    // Fill the screen and let shapes get squashed
+    Stretch,
+    // Keep the shape of the view and leave bars on the sides we dont need
+    Letterbox,
+    // Keep the view height and show more or less of the world to the sides
+    Expand,
+    // Like [Scaling::Letterbox] but only scale by whole numbers so every
+    // view pixel is the same number of screen pixels
+    PixelPerfect,
+}
+
+impl std::str::FromStr for Scaling {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stretch"       => Ok(Scaling::Stretch),
+            "letterbox"     => Ok(Scaling::Letterbox),
+            "expand"        => Ok(Scaling::Expand),
+            "pixel-perfect" => Ok(Scaling::PixelPerfect),
+            _ => Err(format!(
+                "unknown scaling {s:?}, expected stretch, letterbox, expand \
+                or pixel-perfect"
+            )),
+        }
+    }
+}
+
+/// A 2D camera, at a zoom of 1.0 with no rotation it shows the world from
+/// -1.0 to 1.0 up and down around its position, how far it shows to the
+/// sides depends on its [Scaling]
+#[derive(Debug, Clone)]
+pub struct Camera2D {
+    // The point in the world at the centre of the screen
+    pub position: Point,
+    // Where we were before the last tick so we can render between the two
+    previous_position: Point,
+    // Bigger numbers zoom in, 2.0 makes everything twice the size
+    pub zoom: f32,
+    // Radians anticlockwise, the world turns the other way on screen
+    pub rotation: f32,
+    // The entity we follow, if it is despawned we stay where we are
+    pub target: Option<EntityId>,
+    // Half the width and height of a box around the camera the target can
+    // move in without the camera moving
+    pub dead_zone: Point,
+    // Roughly how many seconds we take to catch up with the target, 0.0
+    // snaps straight to it
+    pub smoothing: f32,
+    // How we fit the view onto the screen
+    pub scaling: Scaling,
+}
+
+impl Camera2D {
+    pub fn new(position: Point) -> Self {
+        Self {
+            position,
+            previous_position: position,
+            zoom: 1.0,
+            rotation: 0.0,
+            target: None,
+            dead_zone: CAMERA_DEAD_ZONE,
+            smoothing: CAMERA_SMOOTHING,
+            scaling: Scaling::Expand,
+        }
+    }
+    /// Follow `target` from now on
+    pub fn follow(&mut self, target: EntityId) {
+        self.target = Some(target);
+    }
+    /// Jump straight to `position` without smoothing
+    pub fn teleport(&mut self, position: Point) {
+        self.position = position;
+        self.previous_position = position;
+    }
+    /// Move towards `target` by `dt` seconds. We only move when the target
+    /// leaves the dead zone and then only far enough to put it back on the
+    /// edge, which stops the camera shaking with every small movement
+    fn track(&mut self, target: Point, dt: f32) {
+        let offset = target - self.position;
+        let outside = |offset: f32, half: f32| {
+            if offset > half { offset - half }
+            else if offset < -half { offset + half }
+            else { 0.0 }
+        };
+        let goal = self.position + Point::new(
+            outside(offset.x, self.dead_zone.x),
+            outside(offset.y, self.dead_zone.y),
+        );
+
+        // Exponential smoothing so we move the same at any tick rate
+        let t = if self.smoothing <= 0.0 {
+            1.0
+        } else {
+            1.0 - (-dt / self.smoothing).exp()
+        };
+        self.position = self.position.lerp(&goal, t);
+    }
+    /// The matrix that takes a point from world space to view space, this
+    /// is column major to match WGSL. We move the world so the camera is at
+    /// the origin, turn it the opposite way to the camera then zoom. The
+    /// screen size is dealt with by [super::render::View::projection]
+    pub fn view(&self, alpha: f32) -> [[f32; 4]; 4] {
+        let position = self.previous_position.lerp(&self.position, alpha);
+        let (sin, cos) = (-self.rotation).sin_cos();
+        let (sin, cos) = (sin * self.zoom, cos * self.zoom);
 
+        // Where the rotated and zoomed world origin ends up
+        let x = -(cos * position.x - sin * position.y);
+        let y = -(sin * position.x + cos * position.y);
+
+        [
+            [cos,  sin, 0.0, 0.0],
+            [-sin, cos, 0.0, 0.0],
+            [0.0,  0.0, 1.0, 0.0],
+            [x,    y,   0.0, 1.0],
+        ]
+    }
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Self::new(Point::new(0.0, 0.0))
+    }
+}
+
+/// Move the camera after everything else has moved this tick
 pub fn update(game: &mut Game){
-    // let player = game.entities[game.players[0]].clone();
-    // println!("{:?}", player.xy());
-    // if player.x() >= 1.0 {
-    //     for entity in &mut game.entities {
-    //         if entity.collides() == false { continue }
-    //         entitt.shift
-            
-    //     }
-    // }
+    let camera = &mut game.camera;
+    camera.previous_position = camera.position;
+
+    // Stop following what was despawned and pick up a new player if we
+    // have nothing to follow
+    for event in &game.events {
+        match *event {
+            WorldEvent::Despawned(id) if camera.target == Some(id) => {
+                camera.target = None;
+            },
+            WorldEvent::Spawned(id) if camera.target.is_none()
+                && game.world.players.get(id).is_some() =>
+            {
+                camera.follow(id);
+            },
+            _ => {},
+        }
+    }
+
+    let target = camera.target
+        .and_then(|id| game.world.transforms.get(id))
+        .map(|transform| transform.position);
+    if let Some(target) = target {
+        camera.track(target, game.dt);
+    }
 }
\ No newline at end of file
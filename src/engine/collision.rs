@@ -0,0 +1,367 @@
+//! Here we find entities that overlap and push them apart. We use boxes
+//! around each shape to cheaply find pairs that might touch (broad phase),
+//! then the separating axis theorem to find out if they really do and by how
+//! much (narrow phase)
+//!
+use super::Game;
+use super::entity::{Point, Shape2D, Transform};
+use super::physics::{Physics, State};
+use super::world::{EntityId, World};
+
+/// How much of a push has to be upwards for us to count it as landing on
+/// something, or downwards for hitting our head
+const GROUND_NORMAL_Y: f32 = 0.7;
+/// Pushing two shapes apart can push one into a third, so we go over all the
+/// pairs a few times
+const ITERATIONS: usize = 4;
+
+/// A box lined up with the x and y axis, see [Shape2D::aabb]
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    /// Do the two boxes overlap
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x && other.min.x < self.max.x &&
+        self.min.y < other.max.y && other.min.y < self.max.y
+    }
+}
+
+/// How two shapes overlap
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    // Unit vector pointing from the first shape towards the second
+    pub normal: Point,
+    // How far the shapes need to move apart along the normal
+    pub depth: f32,
+}
+
+/// Find the pairs of boxes that overlap, we sort by the left edge so we only
+/// have to compare each box with the ones that start before it ends
+fn broad_phase(boxes: &[(usize, Aabb)]) -> Vec<(usize, usize)> {
+    let mut sorted: Vec<&(usize, Aabb)> = boxes.iter().collect();
+    sorted.sort_by(|(_, a), (_, b)| a.min.x.total_cmp(&b.min.x));
+
+    let mut pairs = Vec::new();
+    for (i, (a_index, a)) in sorted.iter().enumerate() {
+        for (b_index, b) in &sorted[i + 1..] {
+            // Everything after this starts to the right of us
+            if b.min.x >= a.max.x { break }
+            if a.overlaps(b) {
+                pairs.push((*a_index.min(b_index), *a_index.max(b_index)));
+            }
+        }
+    }
+    pairs
+}
+
+/// Squash the shape onto `axis`, returning the smallest and largest values
+fn project(shape: &Shape2D, axis: &Point) -> (f32, f32) {
+    match shape {
+        Shape2D::Polygon(p) => {
+            p.points.iter()
+                .map(|point| point.dot(axis))
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| {
+                    (min.min(d), max.max(d))
+                })
+        },
+        Shape2D::Circle(c) => {
+            let centre = c.centre.dot(axis);
+            (centre - c.radius, centre + c.radius)
+        },
+    }
+}
+
+/// The axes we need to test for a shape, these are the normals of each side
+/// of a polygon. A circle has no sides so it gives the axis from its centre
+/// to the closest point of the `other` shape
+fn axes(shape: &Shape2D, other: &Shape2D) -> Vec<Point> {
+    match shape {
+        Shape2D::Polygon(p) => {
+            p.points.iter()
+                .zip(p.points.iter().cycle().skip(1))
+                .map(|(a, b)| (*b - *a).perpendicular().normalise())
+                .collect()
+        },
+        Shape2D::Circle(c) => {
+            let closest = match other {
+                Shape2D::Polygon(p) => p.points.iter()
+                    .copied()
+                    .min_by(|a, b| {
+                        (*a - c.centre).length()
+                            .total_cmp(&(*b - c.centre).length())
+                    })
+                    .unwrap_or(c.centre),
+                Shape2D::Circle(o) => o.centre,
+            };
+            vec![(closest - c.centre).normalise()]
+        },
+    }
+}
+
+/// Use the separating axis theorem to find if two shapes overlap, if we can
+/// find an axis where their shadows dont touch then they dont either.
+/// Otherwise the axis with the least overlap is how we push them apart
+pub fn narrow_phase(a: &Shape2D, b: &Shape2D) -> Option<Contact> {
+    let mut contact: Option<Contact> = None;
+
+    for axis in axes(a, b).into_iter().chain(axes(b, a)) {
+        // Circles on top of each other give us no axis
+        if axis.length() == 0.0 { continue }
+
+        let (a_min, a_max) = project(a, &axis);
+        let (b_min, b_max) = project(b, &axis);
+        let depth = a_max.min(b_max) - a_min.max(b_min);
+        if depth <= 0.0 { return None }
+
+        if contact.is_none_or(|c| depth < c.depth) {
+            contact = Some(Contact { normal: axis, depth });
+        }
+    }
+
+    // Make sure the normal points from a to b
+    contact.map(|mut contact| {
+        if (b.centre() - a.centre()).dot(&contact.normal) < 0.0 {
+            contact.normal = contact.normal * -1.0;
+        }
+        contact
+    })
+}
+
+/// Move the entity by `push` and stop it moving any further into what it
+/// hit, if it was pushed up it has landed on something and if it was pushed
+/// down it hit its head so it should start falling
+fn push(transform: &mut Transform, physics: &mut Physics, push: Point) {
+    transform.position = transform.position + push;
+
+    let direction = push.normalise();
+    let body = &mut physics.body;
+    let into = body.velocity.dot(&direction);
+    if into < 0.0 {
+        body.velocity = body.velocity - direction * into;
+    }
+
+    if direction.y >= GROUND_NORMAL_Y {
+        physics.state = State::None;
+    } else if direction.y <= -GROUND_NORMAL_Y && physics.state == State::Jumping {
+        physics.state = State::Falling;
+    }
+}
+
+/// Find every pair of colliding entities and push them apart, static
+/// entities never move so anything hitting them takes the whole push,
+/// otherwise the lighter entity is pushed further. An attached entity that
+/// hits something pushes the top of its tree, which carries it along
+pub fn update(game: &mut Game) {
+    for _ in 0..ITERATIONS {
+        // Attached entities have to be where their tree has moved to
+        game.world.propagate_transforms();
+        if !resolve(&mut game.world) { break }
+    }
+}
+
+/// An entity that can collide, with its shape in world space
+struct Collider {
+    // The top of its tree, which is what moves when it is pushed
+    root: EntityId,
+    shape: Shape2D,
+    inverse_mass: f32,
+}
+
+/// Push apart every overlapping pair once, returns if there were any
+fn resolve(world: &mut World) -> bool {
+    let mut colliders: Vec<Collider> = world.shapes.join(&world.transforms)
+        .filter_map(|(id, shape, transform)| {
+            world.bodies.get(id).filter(|p| p.collides)?;
+            // A whole tree is as heavy as its root, a static root or one
+            // with no body cant be pushed
+            let root = world.root(id);
+            let inverse_mass = match world.bodies.get(root) {
+                Some(physics) if physics.state != State::Static =>
+                    physics.body.inverse_mass(),
+                _ => 0.0,
+            };
+            Some(Collider { root, shape: shape.to_world(transform), inverse_mass })
+        })
+        .collect();
+
+    let boxes: Vec<(usize, Aabb)> = colliders.iter()
+        .enumerate()
+        .map(|(i, collider)| (i, collider.shape.aabb()))
+        .collect();
+
+    let mut resolved = false;
+    for (a, b) in broad_phase(&boxes) {
+        // Entities in the same tree move together so they cant push each
+        // other apart
+        if colliders[a].root == colliders[b].root { continue }
+
+        // Share the push by how heavy each side is
+        let total = colliders[a].inverse_mass + colliders[b].inverse_mass;
+        if total == 0.0 { continue }
+
+        // An earlier push might have already seperated these
+        let contact = match narrow_phase(&colliders[a].shape, &colliders[b].shape) {
+            Some(contact) => contact,
+            None => continue,
+        };
+        let separation = contact.normal * contact.depth;
+        resolved = true;
+
+        for (i, share) in [
+            (a, separation * -(colliders[a].inverse_mass / total)),
+            (b, separation * (colliders[b].inverse_mass / total)),
+        ] {
+            if share.length() == 0.0 { continue }
+            // Everything in the tree moves with its root
+            let root = colliders[i].root;
+            for collider in colliders.iter_mut().filter(|c| c.root == root) {
+                collider.shape = collider.shape.translated(share);
+            }
+
+            let transform = world.transforms.get_mut(root);
+            let physics = world.bodies.get_mut(root);
+            if let (Some(transform), Some(physics)) = (transform, physics) {
+                push(transform, physics, share);
+            }
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::entity::{Circle, Polygon};
+
+    fn square(x: f32, y: f32, size: f32) -> Shape2D {
+        Shape2D::Polygon(Polygon::new(vec![
+            Point::new(x, y),
+            Point::new(x + size, y),
+            Point::new(x + size, y + size),
+            Point::new(x, y + size),
+        ]))
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn overlapping_squares_are_pushed_the_shortest_way() {
+        // b overlaps a by 0.1 to the right and 0.5 up
+        let a = square(0.0, 0.0, 1.0);
+        let b = square(0.9, 0.5, 1.0);
+        let contact = narrow_phase(&a, &b).unwrap();
+        assert!(close(contact.depth, 0.1));
+        assert!(close(contact.normal.x, 1.0) && close(contact.normal.y, 0.0));
+
+        // The normal always points from the first shape to the second
+        let contact = narrow_phase(&b, &a).unwrap();
+        assert!(close(contact.normal.x, -1.0));
+    }
+
+    #[test]
+    fn shapes_that_only_touch_or_are_apart_dont_collide() {
+        let a = square(0.0, 0.0, 1.0);
+        assert!(narrow_phase(&a, &square(1.0, 0.0, 1.0)).is_none());
+        assert!(narrow_phase(&a, &square(3.0, 3.0, 1.0)).is_none());
+        // Their boxes overlap but a circle in the corner misses the square
+        let circle = Shape2D::Circle(Circle::new(Point::new(1.3, 1.3), 0.4));
+        assert!(narrow_phase(&a, &circle).is_none());
+    }
+
+    #[test]
+    fn circles_are_pushed_along_the_line_between_their_centres() {
+        let a = Shape2D::Circle(Circle::new(Point::new(0.0, 0.0), 1.0));
+        let b = Shape2D::Circle(Circle::new(Point::new(1.2, 1.6), 1.5));
+        let contact = narrow_phase(&a, &b).unwrap();
+        assert!(close(contact.depth, 0.5));
+        assert!(close(contact.normal.x, 0.6) && close(contact.normal.y, 0.8));
+    }
+
+    #[test]
+    fn circle_resting_on_a_square_is_pushed_up() {
+        let floor = square(-1.0, -1.0, 1.0);
+        let ball = Shape2D::Circle(Circle::new(Point::new(-0.5, 0.2), 0.25));
+        let contact = narrow_phase(&floor, &ball).unwrap();
+        assert!(close(contact.depth, 0.05));
+        assert!(close(contact.normal.y, 1.0));
+    }
+
+    #[test]
+    fn broad_phase_only_pairs_overlapping_boxes() {
+        let boxes: Vec<(usize, Aabb)> = [
+            square(0.0, 0.0, 1.0),
+            square(0.5, 0.5, 1.0),
+            square(0.5, 3.0, 1.0),
+            square(5.0, 0.0, 1.0),
+        ].iter().map(Shape2D::aabb).enumerate().collect();
+        assert_eq!(broad_phase(&boxes), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn parents_are_not_pushed_by_their_children() {
+        let mut world = World::new();
+        let parent = world.spawn();
+        let hat = world.spawn();
+        let badge = world.spawn();
+        let white = wgpu::Color::WHITE;
+        world.insert_shape(
+            parent, square(0.0, 0.0, 1.0), white, Physics::new(State::None, true)
+        );
+        // Sitting half inside the parent
+        world.insert_shape(
+            hat, square(0.2, 0.5, 0.6), white, Physics::new(State::None, true)
+        );
+        // A static one on the hat that also overlaps the parent
+        world.insert_shape(
+            badge, square(0.4, -0.2, 0.3), white, Physics::default()
+        );
+        world.set_parent(hat, Some(parent)).unwrap();
+        world.set_parent(badge, Some(hat)).unwrap();
+
+        let before = world.transforms.get(parent).copied();
+        assert!(!resolve(&mut world));
+        assert_eq!(world.transforms.get(parent).copied(), before);
+    }
+
+    #[test]
+    fn attached_entities_push_their_tree_out_of_walls() {
+        let mut world = World::new();
+        let floor = world.spawn();
+        let player = world.spawn();
+        let carried = world.spawn();
+        let white = wgpu::Color::WHITE;
+        world.insert_shape(
+            floor, square(-5.0, -10.0, 10.0), white, Physics::default()
+        );
+        // Standing clear of the floor
+        world.insert_shape(
+            player, square(0.0, 0.5, 1.0), white, Physics::new(State::Falling, true)
+        );
+        // Hanging below the player and 0.2 into the floor
+        world.insert_shape(
+            carried, square(0.25, -0.2, 0.5), white, Physics::new(State::None, true)
+        );
+        world.set_parent(carried, Some(player)).unwrap();
+
+        let height = |world: &World, id| {
+            world.transforms.get(id).unwrap().position.y
+        };
+        let before: Vec<f32> = [floor, player, carried].iter()
+            .map(|id| height(&world, *id))
+            .collect();
+        assert!(resolve(&mut world));
+        world.propagate_transforms();
+        assert_eq!(height(&world, floor), before[0]);
+        assert!(close(height(&world, player), before[1] + 0.2));
+        assert!(close(height(&world, carried), before[2] + 0.2));
+        // The player is standing on the floor through what it carries
+        assert_eq!(world.bodies.get(player).unwrap().state, State::None);
+        assert!(!resolve(&mut world));
+    }
+}
@@ -0,0 +1,182 @@
+//! Entities are spawned, despawned and reparented through [Commands] that
+//! are queued while the systems run and applied together at the end of
+//! [super::Game::update], so no system sees an entity appear or vanish part
+//! way through a tick. Each entity that comes or goes, and each parent that
+//! could not be set, fires a [WorldEvent]
+//!
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::animation::SpriteSheet;
+use super::level::EntityDesc;
+use super::world::{EntityId, World};
+
+/// A change to make to the world when the commands are applied
+#[derive(Debug)]
+enum Command {
+    // Give an entity we have already made the components described, boxed
+    // so the other commands dont take up as much room
+    Spawn {
+        id: EntityId,
+        desc: Box<EntityDesc>,
+        sheet: Option<Rc<SpriteSheet>>,
+    },
+    Despawn(EntityId),
+    SetParent { child: EntityId, parent: Option<EntityId> },
+}
+
+/// Something that happened to an entity when the commands were applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldEvent {
+    // It has its components and the systems will see it from the next tick
+    Spawned(EntityId),
+    // It is gone, this is sent for everything attached to it too
+    Despawned(EntityId),
+    // A [Commands::set_parent] we could not do and why, the child is left
+    // attached to what it was before
+    NotAttached { child: EntityId, error: String },
+}
+
+/// Changes to the world waiting for the end of the tick
+#[derive(Debug, Default)]
+pub struct Commands {
+    queue: Vec<Command>,
+    // Entities using the same sheet share it
+    sheets: HashMap<String, Rc<SpriteSheet>>,
+}
+
+impl Commands {
+    /// Queue an entity to be made from `desc`, its handle can be used
+    /// straight away but it has no components until the commands are
+    /// applied. This fails if `desc` makes no sense or has a parent, parents
+    /// are set with [Self::set_parent] instead
+    pub fn spawn(&mut self, world: &mut World, desc: EntityDesc)
+        -> Result<EntityId, String>
+    {
+        desc.validate()?;
+        if desc.parent.is_some() {
+            return Err("parent is where an entity is in a level, use \
+                set_parent for entities made while the game runs".into())
+        }
+        let sheet = desc.load_sheet(&mut self.sheets)?;
+        let id = world.spawn();
+        self.queue.push(Command::Spawn { id, desc: Box::new(desc), sheet });
+        Ok(id)
+    }
+    /// Queue `id` and everything attached to it to be removed
+    pub fn despawn(&mut self, id: EntityId) {
+        self.queue.push(Command::Despawn(id));
+    }
+    /// Queue `child` to be attached to `parent`, or to nothing if it is None
+    pub fn set_parent(&mut self, child: EntityId, parent: Option<EntityId>) {
+        self.queue.push(Command::SetParent { child, parent });
+    }
+    /// Make every queued change in the order they were queued, adding what
+    /// happened to `events`. A spawn or despawn of an entity that has
+    /// already gone is skipped
+    pub fn apply(&mut self, world: &mut World, events: &mut Vec<WorldEvent>) {
+        for command in self.queue.drain(..) {
+            match command {
+                Command::Spawn { id, desc, sheet } => {
+                    if !world.is_alive(id) { continue }
+                    desc.insert(world, id, sheet);
+                    events.push(WorldEvent::Spawned(id));
+                },
+                Command::Despawn(id) => {
+                    events.extend(
+                        world.despawn(id).into_iter().map(WorldEvent::Despawned)
+                    );
+                },
+                Command::SetParent { child, parent } => {
+                    if let Err(error) = world.set_parent(child, parent) {
+                        events.push(WorldEvent::NotAttached { child, error });
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::entity::{Point, Transform};
+    use crate::engine::level::ShapeDesc;
+
+    fn desc() -> EntityDesc {
+        EntityDesc::new(ShapeDesc::Circle {
+            centre: Point::new(0.0, 0.0),
+            radius: 1.0,
+        }, [1.0; 4])
+    }
+
+    #[test]
+    fn spawns_wait_until_the_commands_are_applied() {
+        let mut world = World::new();
+        let mut commands = Commands::default();
+        let mut events = Vec::new();
+        let id = commands.spawn(&mut world, desc()).unwrap();
+        assert!(world.is_alive(id));
+        assert!(world.shapes.get(id).is_none());
+
+        commands.apply(&mut world, &mut events);
+        assert!(world.shapes.get(id).is_some());
+        assert_eq!(events, [WorldEvent::Spawned(id)]);
+        // Applying again does nothing
+        commands.apply(&mut world, &mut events);
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn bad_spawns_are_refused_straight_away() {
+        let mut world = World::new();
+        let mut commands = Commands::default();
+        let bad = EntityDesc { rotation: f32::NAN, ..desc() };
+        assert!(commands.spawn(&mut world, bad).is_err());
+        let parented = EntityDesc { parent: Some(0), ..desc() };
+        assert!(commands.spawn(&mut world, parented).is_err());
+        assert!(world.entities().is_empty());
+    }
+
+    #[test]
+    fn despawns_take_everything_attached_with_them() {
+        let mut world = World::new();
+        let mut commands = Commands::default();
+        let mut events = Vec::new();
+        let parent = commands.spawn(&mut world, desc()).unwrap();
+        let child = commands.spawn(&mut world, desc()).unwrap();
+        commands.set_parent(child, Some(parent));
+        commands.apply(&mut world, &mut events);
+        assert_eq!(world.parents.get(child).map(|p| p.id), Some(parent));
+
+        events.clear();
+        commands.despawn(parent);
+        // The second one is skipped as it has already gone
+        commands.despawn(child);
+        commands.apply(&mut world, &mut events);
+        assert_eq!(events, [
+            WorldEvent::Despawned(parent),
+            WorldEvent::Despawned(child),
+        ]);
+        assert!(!world.is_alive(parent) && !world.is_alive(child));
+    }
+
+    #[test]
+    fn parents_that_cant_be_set_say_why() {
+        let mut world = World::new();
+        let mut commands = Commands::default();
+        let mut events = Vec::new();
+        let parent = world.spawn();
+        let child = world.spawn();
+        world.transforms.insert(parent, Transform::new(Point::new(0.0, 0.0)));
+        world.set_parent(child, Some(parent)).unwrap();
+
+        // Attaching the parent to its own child would make a loop
+        commands.set_parent(parent, Some(child));
+        commands.apply(&mut world, &mut events);
+        assert!(matches!(
+            &events[..],
+            [WorldEvent::NotAttached { child: id, .. }] if *id == parent
+        ));
+        assert!(world.parents.get(parent).is_none());
+    }
+}
@@ -1,55 +1,100 @@
-use std::collections::HashMap;
-
-use winit::event::VirtualKeyCode;
-use crate::engine::physics::State;
-use crate::globals::{JUMP_TICKS, PLAYER_SPEED};
+use crate::engine::entity::Player;
+use crate::engine::physics::{Physics, State};
+use crate::globals::{JUMP_BUFFER_TICKS, JUMP_CUT, JUMP_SPEED, PLAYER_SPEED};
 use super::game::Game;
-use super::entity::{Shape2D, Transform2D, Entity};
-/// handle keypresses
+use super::input::Action;
+/// Move a player `direction` sideways and jump if they asked to recently
 /// 
-fn match_key(
-    player: &mut Shape2D, 
-    keys_down: &HashMap<Option<VirtualKeyCode>, u8>, 
-    dt: f32
+fn apply_actions(
+    player: &mut Player,
+    physics: &mut Physics,
+    direction: f32,
+    jump: bool,
+    jump_released: bool,
 ) {
-    for key in keys_down.keys() {
-        match key {
-            // Move right
-            Some(VirtualKeyCode::D) | Some(VirtualKeyCode::Right)  => { 
-                // Handle edge of screen
-                if player.x() >= 1.1 { player.set_x(-1.1) }
-                player.shift_x(PLAYER_SPEED * dt); 
-                 
-            },
-            // Move Left
-            Some(VirtualKeyCode::A) | Some(VirtualKeyCode::Left)  => { 
-                // Handle edge of screen
-                if player.x() <= -1.1 { player.set_x(1.1) }
-                player.shift_x(-PLAYER_SPEED * dt);
-            },
-            // Jump
-            Some(VirtualKeyCode::W) | Some(VirtualKeyCode::Space)  => {
-                if player.state() == State::None {
-                    player.set_state(State::Jumping(JUMP_TICKS))
-                }
-            },
-            Some(key) => {
-                println!("We dont handle {key:?}");
-            },
-            None => todo!("WTF: {key:?}")
-        };
+    // We only move sideways while an action is held, holding both ways
+    // cancels out
+    physics.body.velocity.x = direction * PLAYER_SPEED;
+
+    // Remember the jump for a while in case we are still in the air
+    if jump {
+        player.jump_buffer = JUMP_BUFFER_TICKS;
+    }
+
+    // We can only jump off something we are standing on
+    if player.jump_buffer > 0 && physics.state == State::None {
+        physics.state = State::Jumping;
+        physics.body.velocity.y = JUMP_SPEED;
+        player.jump_buffer = 0;
+    }
+    player.jump_buffer = player.jump_buffer.saturating_sub(1);
+
+    // Letting go of jump on the way up makes it a smaller jump
+    if jump_released && physics.state == State::Jumping {
+        physics.body.velocity.y *= JUMP_CUT;
     }
 }
-/// Run logic on the inputs in [keys_down] HasMap
+/// Move every player by the [Action]s held down, see [Game::bindings] for
+/// which inputs trigger them
 /// 
 pub fn update(game: &mut Game){
-    // We handle our player no matter what shape
+    // Sticks let us move slower than full speed
+    let direction = game.action_value(Action::MoveRight)
+        - game.action_value(Action::MoveLeft);
+    // Holding jump does not jump again when we land, it has to be pressed
+    let jump = game.actions.just_pressed(Action::Jump);
+    let jump_released = game.actions.just_released(Action::Jump);
+
+    // We handle every player no matter what shape, if we have no players
+    // there is no input handling
+    let world = &mut game.world;
+    for (_, player, physics) in world.players.join_mut(&mut world.bodies) {
+        apply_actions(player, physics, direction, jump, jump_released)
+    }
+}
 
-    // If we have no players, dont do any input handling
-    if game.players.len() == 0 { return }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let player = &mut game.entities[game.players[0]];
+    /// Run `apply_actions` once for each tick in `jumps`, which says if jump
+    /// was pressed that tick, landing on the ground at tick `land`
+    fn run(jumps: &[bool], land: usize) -> Physics {
+        let mut player = Player::default();
+        let mut physics = Physics::new(State::Falling, true);
+        for (tick, jump) in jumps.iter().enumerate() {
+            if tick == land {
+                physics.state = State::None;
+            }
+            apply_actions(&mut player, &mut physics, 0.0, *jump, false);
+        }
+        physics
+    }
 
-    match_key(player, &game.keys_down, game.dt)
+    #[test]
+    fn jumps_pressed_just_before_landing_still_happen() {
+        let mut jumps = vec![false; JUMP_BUFFER_TICKS as usize + 5];
+        jumps[1] = true;
+        let physics = run(&jumps, JUMP_BUFFER_TICKS as usize);
+        assert_eq!(physics.state, State::Jumping);
+        assert_eq!(physics.body.velocity.y, JUMP_SPEED);
+    }
 
+    #[test]
+    fn jumps_pressed_too_long_before_landing_are_forgotten() {
+        let mut jumps = vec![false; JUMP_BUFFER_TICKS as usize + 5];
+        jumps[1] = true;
+        let physics = run(&jumps, JUMP_BUFFER_TICKS as usize + 1);
+        assert_eq!(physics.state, State::None);
+    }
+
+    #[test]
+    fn letting_go_on_the_way_up_cuts_the_jump() {
+        let mut player = Player::default();
+        let mut physics = Physics::new(State::None, true);
+        apply_actions(&mut player, &mut physics, 1.0, true, false);
+        assert_eq!(physics.body.velocity.x, PLAYER_SPEED);
+        apply_actions(&mut player, &mut physics, 1.0, false, true);
+        assert_eq!(physics.body.velocity.y, JUMP_SPEED * JUMP_CUT);
+    }
 }
\ No newline at end of file
@@ -1,25 +1,35 @@
-//! Here is our entity system, we describe our "Entities" that are objects
-//! inside our game, we also do the maths on how to move them here. We define
-//! how we want our objects to behave here
+//! Here we describe the components that make up our "Entities", the objects
+//! inside our game, we also do the maths on how to move them here. The
+//! entities themselves live in [super::world::World]
 
-use super::physics::{Physics, State};
+use serde::{Deserialize, Serialize};
+use super::collision::Aabb;
+use super::world::EntityId;
 use std::f32::consts::PI;
 
-#[derive(Debug, Clone, Copy)]
+/// How many triangles we cut a [Circle] into when we draw it
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Shape2D{
-    Triangle(Triangle),
-    Rectangle(Rectangle),
-    Pentagon(Pentagon),
+    Polygon(Polygon),
+    Circle(Circle),
+}
+/// A shape cut into triangles to draw, every three indices are the corners
+/// of a triangle. Corners shared by triangles are only stored once
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub points: Vec<Point>,
+    pub indices: Vec<u32>,
 }
+
 /// Here are traits that must be implemented for each object
 /// to move it in 2d space
 pub trait Transform2D {
-    /// Return the x value of the bottom left `C`
+    /// Return the x value of the bottom left of the shape
     fn x(&self) -> f32;
-    /// Return the y value of the bottom left `C`
+    /// Return the y value of the bottom left of the shape
     fn y(&self) -> f32;
-    /// Return the x,y of the bottom left `C`
-    fn xy(&self) -> Point;
     /// Translate just the x coord
     fn shift_x(&mut self, x: f32);
     /// Translate just the y coord
@@ -29,152 +39,215 @@ pub trait Transform2D {
         self.shift_x(x);
         self.shift_y(y);
     }
-    /// Set the x coord to an arbitory value
-    fn set_x(&mut self, x: f32);
-    /// Set the y coord to an arbitory value
-    fn set_y(&mut self, y: f32);
-    /// Set the x,y coords to arbitory values
-    fn set_xy(&mut self, x: f32, y: f32) {
-        self.set_x(x);
-        self.set_y(y);
-    }
     // Find the y value with the highest value
-    fn max_y(&self) -> f32;    
+    fn max_y(&self) -> f32;
     // Find the x value with the highest value
     fn max_x(&self) -> f32;
 }
 
-/// Here are traits that are applied to entities such as
-/// managing [super::physics::State]
-pub trait Entity {
-    // Get the state
-    fn state(&self) -> State;
-    // Set the state 
-    fn set_state(&mut self, state: State);
-    // Get the collision status
-    fn collides(&self) -> bool;
+/// Our respresentation of any convex shape with straight sides, the points
+/// go around the outside of the shape in order either way. Collisions and
+/// [Shape2D::contains] only work for convex shapes, but we move every shape
+/// each tick so this is not checked here. Anything made from outside the
+/// game, like a level, has to pass [Polygon::is_convex] first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon{
+    pub points: Vec<Point>,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Rectangle{
-    pub a: Point,
-    pub b: Point,
-    pub c: Point,
-    pub d: Point,
-    pub colour: wgpu::Color,
-    physics: Physics,
-}
-
-impl Rectangle{
-    pub fn new(
-        a: Point, b: Point, c: Point, d: Point, 
-        colour: wgpu::Color, 
-        state: State
-    ) -> Self {
-        Self { 
-            a, b, c, d, 
-            colour, 
-            physics: Physics::new(state, true) 
+impl Polygon{
+    pub fn new(points: Vec<Point>) -> Self {
+        assert!(points.len() >= 3, "A polygon needs at least 3 points");
+        Self { points }
+    }
+    /// C is the bottom Left of the screen
+    /// |
+    /// |   A
+    /// | /   \
+    /// |/Black\
+    /// C-------B-------------
+    pub fn triangle(a: Point, b: Point, c: Point) -> Self {
+        Self::new(vec![a, b, c])
+    }
+    /// C is the bottom Left of the screen
+    /// |
+    /// |
+    /// A-------B
+    /// | Black |
+    /// C-------D-------------
+    pub fn rectangle(a: Point, b: Point, c: Point, d: Point) -> Self {
+        // Go round the outside rather than across the middle
+        Self::new(vec![a, b, d, c])
+    }
+    /// reference: https://mathworld.wolfram.com/RegularPentagon.html
+    /// |    B
+    /// |  /   \
+    /// |A/     \D
+    /// | \Black/
+    /// |  \___/
+    /// ---C---E--------------
+    /// `centre` is the middle of the pentagon and `radius` is the distance
+    /// from there to each point
+    pub fn pentagon(centre: Point, radius: f32) -> Self {
+        // Start at the top B and go round clockwise
+        let points = (0..5)
+            .map(|i| {
+                let angle = PI / 2.0 - i as f32 * 2.0 * PI / 5.0;
+                Point::new(
+                    centre.x + radius * angle.cos(),
+                    centre.y + radius * angle.sin(),
+                )
+            })
+            .collect();
+        Self::new(points)
+    }
+    /// Do the points go round a convex shape, every corner must turn the
+    /// same way and the turns must add up to one full turn. A star turns the
+    /// same way at every corner but goes round more than once
+    pub fn is_convex(points: &[Point]) -> bool {
+        let mut sign = 0.0;
+        let mut turned = 0.0;
+        for i in 0..points.len() {
+            let a = points[i];
+            let b = points[(i + 1) % points.len()];
+            let c = points[(i + 2) % points.len()];
+            let cross = (b.x - a.x) * (c.y - b.y) - (b.y - a.y) * (c.x - b.x);
+            let dot = (b.x - a.x) * (c.x - b.x) + (b.y - a.y) * (c.y - b.y);
+            turned += cross.atan2(dot);
+            if cross == 0.0 { continue }
+            if sign == 0.0 {
+                sign = cross.signum();
+            } else if cross.signum() != sign {
+                return false
+            }
         }
+        // All the points were in a line if we never turned
+        sign != 0.0 && (turned.abs() - std::f32::consts::TAU).abs() < 1e-3
     }
-}
-/// C is the bottom Left of the screen
-/// |
-/// |
-/// A-------B
-/// | Black |
-/// C-------D-------------
-/// Its actually a square by default
-impl Default for Rectangle{
-    fn default() -> Self {
-        Self { 
-            a:          Point::new(-1.0, -0.9), // A
-            b:          Point::new(-0.9, -0.9), // B
-            c:          Point::new(-1.0, -1.0), // C
-            d:          Point::new(-0.9, -1.0), // D
-            colour:     wgpu::Color::BLACK,
-            physics:    Physics::default(),
+    /// Cut the polygon into triangles to draw, see [triangulate]
+    pub fn mesh(&self) -> Mesh {
+        Mesh {
+            points: self.points.clone(),
+            indices: triangulate(&self.points),
         }
     }
 }
-// Our respresentation of a Pentagon entity
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-pub struct Pentagon{
-    pub a: Point,
-    pub b: Point,
-    pub c: Point,
-    pub d: Point,
-    pub e: Point,
-    pub colour: wgpu::Color,
-    physics: Physics,
+
+/// Ear clipping, a corner that turns the same way as the polygon with no
+/// other points inside its triangle is an ear. We cut ears off one at a time
+/// until there is one triangle left, returning the corners of every triangle
+/// as indices into `points`
+fn triangulate(points: &[Point]) -> Vec<u32> {
+    // Twice the area, positive if the points go anticlockwise
+    let area: f32 = points.iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum();
+    let turn = |a: Point, b: Point, c: Point| {
+        ((b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)) * area.signum()
+    };
+
+    let mut left: Vec<u32> = (0..points.len() as u32).collect();
+    let mut indices = Vec::with_capacity((points.len() - 2) * 3);
+    while left.len() > 3 {
+        let n = left.len();
+        let corner = |i: usize| {
+            [left[(i + n - 1) % n], left[i], left[(i + 1) % n]]
+        };
+        let is_ear = |i: usize| {
+            let [a, b, c] = corner(i).map(|index| points[index as usize]);
+            if turn(a, b, c) <= 0.0 { return false }
+            // Points on the edge count as inside, but not ones on a corner
+            !left.iter()
+                .map(|index| points[*index as usize])
+                .filter(|p| *p != a && *p != b && *p != c)
+                .any(|p| turn(a, b, p) >= 0.0 && turn(b, c, p) >= 0.0
+                    && turn(c, a, p) >= 0.0)
+        };
+        // Sides that cross can leave no ears, we cut any corner so we still
+        // finish even if the result looks wrong
+        let ear = (0..n).find(|i| is_ear(*i)).unwrap_or(0);
+        indices.extend(corner(ear));
+        left.remove(ear);
+    }
+    indices.extend(left);
+    indices
 }
-/// reference: https://mathworld.wolfram.com/RegularPentagon.html
-/// C is the bottom Left of the screen
-/// |    B
-/// |  /   \
-/// |A/     \D
-/// | \Black/
-/// |  \___/
-/// ---C---E--------------
-/// ^^default^^
-impl Default for Pentagon{
-    fn default() -> Self {
-        // Probably should precompute this, or calculate before game starts
-        const B_X: f32 = 0.0 / 10.0;
-        const B_Y: f32 = 1.0 / 10.0;
-        let c1 = f32::cos(2.0 * PI / 5.0) / 10.0;
-        let c2 = f32::cos(      PI / 5.0) / 10.0;
-        let s1 = f32::sin(2.0 * PI / 5.0) / 10.0;
-        let s2 = f32::sin(4.0 * PI / 5.0) / 10.0;
 
-        Self {
-            a: Point::new(-s1,  c1), // A
-            b: Point::new(   B_X,  B_Y), // B
-            c: Point::new(-s2,  -c2), // C
-            d: Point::new( s1,  c1), // D
-            e: Point::new( s2,  -c2), // E
-            colour: wgpu::Color::BLACK,
-            physics: Physics::default(),
-        }
+impl Transform2D for Polygon {
+    fn x(&self) -> f32 {
+        self.points.iter().map(|p| p.x).fold(f32::INFINITY, f32::min)
+    }
+    fn y(&self) -> f32 {
+        self.points.iter().map(|p| p.y).fold(f32::INFINITY, f32::min)
+    }
+    fn shift_x(&mut self, x: f32) {
+        for point in &mut self.points { point.x += x }
+    }
+    fn shift_y(&mut self, y: f32) {
+        for point in &mut self.points { point.y += y }
+    }
+    fn max_y(&self) -> f32 {
+        self.points.iter().map(|p| p.y).fold(f32::NEG_INFINITY, f32::max)
+    }
+    fn max_x(&self) -> f32 {
+        self.points.iter().map(|p| p.x).fold(f32::NEG_INFINITY, f32::max)
     }
 }
-// Our respresentation of a triangle entity
-#[derive(Debug, Clone, Copy)]
-pub struct Triangle{
-    pub a: Point,
-    pub b: Point,
-    pub c: Point,
-    pub colour: wgpu::Color,
-    physics: Physics,
+
+/// Our respresentation of a circle
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circle{
+    pub centre: Point,
+    pub radius: f32,
 }
-/// C is the bottom Left of the screen
-/// |
-/// |   A
-/// | /   \
-/// |/Black\
-/// C-------B-------------
-/// ^^default^^
-impl Default for Triangle{
-    fn default() -> Self {
-        Self { 
-            a: Point::new(-0.95, -0.9), // A
-            b: Point::new(-0.9,  -1.0), // B
-            c: Point::new(-1.0,  -1.0), // C
-            colour: wgpu::Color::BLACK,
-            physics: Physics::default(),
+
+impl Circle{
+    pub fn new(centre: Point, radius: f32) -> Self {
+        Self { centre, radius }
+    }
+    /// Cut the circle into [CIRCLE_SEGMENTS] triangles fanning out from the
+    /// centre, which is the first point
+    pub fn mesh(&self) -> Mesh {
+        let edge = (0..CIRCLE_SEGMENTS).map(|i| {
+            let angle = i as f32 * 2.0 * PI / CIRCLE_SEGMENTS as f32;
+            Point::new(
+                self.centre.x + self.radius * angle.cos(),
+                self.centre.y + self.radius * angle.sin(),
+            )
+        });
+        let segments = CIRCLE_SEGMENTS as u32;
+        Mesh {
+            points: std::iter::once(self.centre).chain(edge).collect(),
+            indices: (0..segments)
+                .flat_map(|i| [0, i + 1, (i + 1) % segments + 1])
+                .collect(),
         }
     }
 }
 
-impl Triangle{
-    pub fn new(a: Point, b: Point, c: Point, 
-        colour: wgpu::Color, physics: Physics) -> Self {
-        Self { a, b, c, colour, physics }
+impl Transform2D for Circle {
+    fn x(&self) -> f32 {
+        self.centre.x - self.radius
+    }
+    fn y(&self) -> f32 {
+        self.centre.y - self.radius
+    }
+    fn shift_x(&mut self, x: f32) {
+        self.centre.x += x;
+    }
+    fn shift_y(&mut self, y: f32) {
+        self.centre.y += y;
+    }
+    fn max_y(&self) -> f32 {
+        self.centre.y + self.radius
+    }
+    fn max_x(&self) -> f32 {
+        self.centre.x + self.radius
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -184,175 +257,500 @@ impl Point{
     pub fn new(x: f32, y: f32) -> Self {
         Self { x, y }
     }
+    /// The dot product of two points treated as vectors
+    pub fn dot(&self, other: &Point) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+    /// The length of the point treated as a vector
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    /// Scale the vector to a length of 1, a zero vector stays zero
+    pub fn normalise(&self) -> Point {
+        let length = self.length();
+        if length == 0.0 { return *self }
+        Point::new(self.x / length, self.y / length)
+    }
+    /// The vector at a right angle to this one
+    pub fn perpendicular(&self) -> Point {
+        Point::new(-self.y, self.x)
+    }
+    /// The vector turned anticlockwise by `angle` radians
+    pub fn rotated(&self, angle: f32) -> Point {
+        let (sin, cos) = angle.sin_cos();
+        Point::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
+    /// Linearly interpolate from `self` towards `other` by `t`
+    pub fn lerp(&self, other: &Point, t: f32) -> Point {
+        Point::new(
+            self.x + (other.x - self.x) * t,
+            self.y + (other.y - self.y) * t,
+        )
+    }
 }
 
-/// See [Transform2D] for comments 
-impl Transform2D for Shape2D {
-    fn x(&self) -> f32 {
-        match &self {
-            Shape2D::Triangle(t) => { t.c.x },
-            Shape2D::Rectangle(r) => { r.c.x },
-            Shape2D::Pentagon(_) => { unimplemented!() }
-        }
+impl std::ops::Add for Point {
+    type Output = Point;
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y)
     }
-    fn y(&self) -> f32 {
-        match &self {
-            Shape2D::Triangle(t) => { t.c.y },
-            Shape2D::Rectangle(r) => { r.c.y },
-            Shape2D::Pentagon(_) => { unimplemented!() }
-        }
+}
+
+impl std::ops::Sub for Point {
+    type Output = Point;
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl std::ops::Mul<f32> for Point {
+    type Output = Point;
+    fn mul(self, scale: f32) -> Point {
+        Point::new(self.x * scale, self.y * scale)
     }
-    fn xy(&self) -> Point {
-        match &self {
-            Shape2D::Triangle(t) => { Point { x: t.c.x, y: t.c.y } },
-            Shape2D::Rectangle(r) => { Point { x: r.c.x, y: r.c.y } },
-            Shape2D::Pentagon(_) => { unimplemented!() }
+}
+
+impl Shape2D {
+    /// The box around the shape, lined up with the x and y axis
+    pub fn aabb(&self) -> Aabb {
+        Aabb {
+            min: Point::new(self.x(), self.y()),
+            max: Point::new(self.max_x(), self.max_y()),
         }
     }
-    fn set_x(&mut self, x: f32) {
+    /// The middle of the shape, for a polygon this is the average of its
+    /// points
+    pub fn centre(&self) -> Point {
         match self {
-            Shape2D::Triangle(ref mut t) => {
-                let ca_side_y = t.a.x - t.c.x;
-                let cb_side_x = t.b.x - t.c.x;
-                t.a.x = x + ca_side_y;
-                t.b.x = x + cb_side_x;
-                t.c.x = x;
-            },
-            Shape2D::Rectangle(ref mut r) => { 
-                let width = r.b.x - r.a.x;
-                r.a.x = x;
-                r.b.x = x + width;
-                r.c.x = x;
-                r.d.x = x + width;
+            Shape2D::Polygon(p) => {
+                let sum = p.points.iter()
+                    .fold(Point::new(0.0, 0.0), |sum, point| sum + *point);
+                sum * (1.0 / p.points.len() as f32)
             },
-            Shape2D::Pentagon(_) => { unimplemented!() }
+            Shape2D::Circle(c) => c.centre,
         }
     }
-    fn set_y(&mut self, y: f32) {
+    /// A copy of the shape moved by `offset`
+    pub fn translated(&self, offset: Point) -> Shape2D {
+        let mut shape = self.clone();
+        shape.shift_xy(offset.x, offset.y);
+        shape
+    }
+    /// The shape moved from its own local space to where `transform` puts it
+    /// in the world
+    pub fn to_world(&self, transform: &Transform) -> Shape2D {
         match self {
-            Shape2D::Triangle(ref mut t) => {
-                let ca_side_y = t.a.y - t.c.y;
-                let cb_side_y = t.b.y - t.c.y;
-                t.a.y = y + ca_side_y;
-                t.b.y = y + cb_side_y;
-                t.c.y = y;
-            },
-            Shape2D::Rectangle(ref mut r) => {
-                let height = r.a.y - r.c.y;
-                r.a.y = y + height;
-                r.b.y = y + height;
-                r.c.y = y;
-                r.d.y = y;
+            Shape2D::Polygon(p) => Shape2D::Polygon(Polygon::new(
+                p.points.iter().map(|point| transform.apply(*point)).collect()
+            )),
+            Shape2D::Circle(c) => {
+                let transform = self.fitted(transform);
+                Shape2D::Circle(Circle::new(
+                    transform.apply(c.centre),
+                    c.radius * transform.scale.x.abs(),
+                ))
             },
-            Shape2D::Pentagon(_) => { unimplemented!() }
         }
     }
-    fn shift_x(&mut self, x: f32) {
+    /// Where a `point` in the shape's local space, like one from its
+    /// [Self::mesh], is in the world. This agrees with [Self::to_world]
+    pub fn point_to_world(&self, point: Point, transform: &Transform) -> Point {
+        self.fitted(transform).apply(point)
+    }
+    /// The transform to use for this shape. Circles cant be stretched into
+    /// ovals so they are scaled the same both ways, by the larger scale
+    fn fitted(&self, transform: &Transform) -> Transform {
         match self {
-            Shape2D::Triangle(ref mut t) => {
-                t.a.x += x;
-                t.b.x += x;
-                t.c.x += x;
-            },
-            Shape2D::Rectangle(ref mut r) => {
-                r.a.x += x;
-                r.b.x += x;
-                r.c.x += x;
-                r.d.x += x;
+            Shape2D::Polygon(_) => *transform,
+            Shape2D::Circle(_) => {
+                let Point { x, y } = transform.scale;
+                let scale = x.abs().max(y.abs());
+                Transform {
+                    scale: Point::new(scale * x.signum(), scale * y.signum()),
+                    ..*transform
+                }
             },
-            Shape2D::Pentagon(_) => { unimplemented!() }
         }
     }
-    fn shift_y(&mut self, y: f32) {
+    /// Is `point` inside the shape, points on the edge count as inside
+    pub fn contains(&self, point: Point) -> bool {
         match self {
-            Shape2D::Triangle(ref mut t) => {
-                t.a.y += y;
-                t.b.y += y;
-                t.c.y += y;
-            },
-            Shape2D::Rectangle(ref mut r) => {
-                r.a.y += y;
-                r.b.y += y;
-                r.c.y += y;
-                r.d.y += y;
+            Shape2D::Polygon(p) => {
+                // The point has to be on the same side of every edge, we
+                // dont know which way round the points go so either side
+                let sides = p.points.iter()
+                    .zip(p.points.iter().cycle().skip(1))
+                    .map(|(a, b)| (*b - *a).perpendicular().dot(&(point - *a)));
+                let (mut left, mut right) = (false, false);
+                for side in sides {
+                    left |= side > 0.0;
+                    right |= side < 0.0;
+                }
+                !(left && right)
             },
-            Shape2D::Pentagon(_) => { unimplemented!() }
+            Shape2D::Circle(c) => (point - c.centre).length() <= c.radius,
         }
     }
-    fn max_y(&self) -> f32 {
-        match &self {
-            Shape2D::Triangle(_) => { unimplemented!() },
-            Shape2D::Rectangle(r) => { 
-                let mut max = r.a.y;
-                if r.b.y > max { max = r.b.y }
-                if r.c.y > max { max = r.c.y }
-                if r.d.y > max { max = r.d.y }
-                max
-            },
-            Shape2D::Pentagon(_) => { unimplemented!() }
+    /// The triangles we draw this shape with
+    pub fn mesh(&self) -> Mesh {
+        match self {
+            Shape2D::Polygon(p) => p.mesh(),
+            Shape2D::Circle(c) => c.mesh(),
         }
     }
-    fn max_x(&self) -> f32 {
-        match &self {
-            Shape2D::Triangle(t) => { 
-                let mut max = t.a.x;
-                if t.b.x > max { max = t.b.x }
-                if t.c.x > max { max = t.c.x }
-                max
-             },
-            Shape2D::Rectangle(r) => { 
-                let mut max = r.a.x;
-                if r.b.x > max { max = r.b.x }
-                if r.c.x > max { max = r.c.x }
-                if r.d.x > max { max = r.d.x }
-                max
-            },
-            Shape2D::Pentagon(_) => { unimplemented!() }
+    fn transform(&self) -> &dyn Transform2D {
+        match self {
+            Shape2D::Polygon(p) => p,
+            Shape2D::Circle(c) => c,
+        }
+    }
+    fn transform_mut(&mut self) -> &mut dyn Transform2D {
+        match self {
+            Shape2D::Polygon(p) => p,
+            Shape2D::Circle(c) => c,
         }
     }
 }
 
-impl Entity for Shape2D {
-    // Get the state
-    fn state(&self) -> State {
-        match self {
-            Shape2D::Triangle(t) => {
-                t.physics.state
-            },
-            Shape2D::Rectangle(r) => {
-                r.physics.state
-            },
-            Shape2D::Pentagon(p) => {
-                p.physics.state
-            },
+/// See [Transform2D] for comments
+impl Transform2D for Shape2D {
+    fn x(&self) -> f32 {
+        self.transform().x()
+    }
+    fn y(&self) -> f32 {
+        self.transform().y()
+    }
+    fn shift_x(&mut self, x: f32) {
+        self.transform_mut().shift_x(x)
+    }
+    fn shift_y(&mut self, y: f32) {
+        self.transform_mut().shift_y(y)
+    }
+    fn max_y(&self) -> f32 {
+        self.transform().max_y()
+    }
+    fn max_x(&self) -> f32 {
+        self.transform().max_x()
+    }
+}
+
+/// Where an entity is in the world, its [Shape2D] is in its own local space
+/// and this takes it into the world. The shape is scaled then rotated about
+/// the pivot and then moved by the position, so changing the pivot only
+/// changes what the shape turns around
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub position: Point,
+    // Anticlockwise in radians
+    pub rotation: f32,
+    // How much the shape is stretched along its own x and y, a negative
+    // scale mirrors it
+    pub scale: Point,
+    // In local space, where the shape is rotated and scaled about
+    pub pivot: Point,
+}
+
+impl Transform {
+    pub fn new(position: Point) -> Self {
+        Self {
+            position,
+            rotation: 0.0,
+            scale: Point::new(1.0, 1.0),
+            pivot: Point::new(0.0, 0.0),
         }
     }
-    // Set the state 
-    fn set_state(&mut self, state: State){
-        match self {
-            Shape2D::Triangle(ref mut t) => {
-                t.physics.state = state
-            },
-            Shape2D::Rectangle(ref mut r) => {
-                r.physics.state = state
-            },
-            Shape2D::Pentagon(ref mut p) => {
-                p.physics.state = state
-            },
+    /// Where `point` in local space is in the world
+    pub fn apply(&self, point: Point) -> Point {
+        let offset = point - self.pivot;
+        let scaled = Point::new(offset.x * self.scale.x, offset.y * self.scale.y);
+        self.position + self.pivot + scaled.rotated(self.rotation)
+    }
+    /// Where `point` in the world is in local space, this undoes
+    /// [Self::apply] and is not finite if the scale is 0.0 either way
+    pub fn unapply(&self, point: Point) -> Point {
+        let rotated = (point - self.position - self.pivot).rotated(-self.rotation);
+        let offset = Point::new(rotated.x / self.scale.x, rotated.y / self.scale.y);
+        self.pivot + offset
+    }
+    /// Where a child whose transform is `local` to this one is in the world.
+    /// The scales are multiplied along each axis, so a child turned inside a
+    /// parent stretched more one way than the other is not skewed like its
+    /// shape would be
+    pub fn combine(&self, local: &Transform) -> Transform {
+        Transform {
+            // Put the child's pivot where the parent takes it
+            position: self.apply(local.position + local.pivot) - local.pivot,
+            rotation: self.rotation + local.rotation,
+            scale: Point::new(
+                self.scale.x * local.scale.x,
+                self.scale.y * local.scale.y,
+            ),
+            pivot: local.pivot,
         }
     }
-    // Get if the object collides
-    fn collides(&self) -> bool {
-        match self {
-            Shape2D::Triangle(t) => {
-                t.physics.collides
-            },
-            Shape2D::Rectangle(r) => {
-                r.physics.collides
-            },
-            Shape2D::Pentagon(p) => {
-                p.physics.collides
-            },
+    /// The transform local to this one that puts a child at `world`, this
+    /// undoes [Self::combine] and like [Self::unapply] needs a scale that is
+    /// not 0.0
+    pub fn relative(&self, world: &Transform) -> Transform {
+        Transform {
+            position: self.unapply(world.position + world.pivot) - world.pivot,
+            rotation: world.rotation - self.rotation,
+            scale: Point::new(
+                world.scale.x / self.scale.x,
+                world.scale.y / self.scale.y,
+            ),
+            pivot: world.pivot,
+        }
+    }
+    /// Blend between the `previous` tick's transform and this one, `alpha` of
+    /// 0.0 is `previous` and 1.0 is `self`
+    pub fn interpolate(&self, previous: &Transform, alpha: f32) -> Transform {
+        Transform {
+            position: previous.position.lerp(&self.position, alpha),
+            rotation: previous.rotation
+                + (self.rotation - previous.rotation) * alpha,
+            scale: previous.scale.lerp(&self.scale, alpha),
+            pivot: self.pivot,
+        }
+    }
+}
+
+/// Draws part of an image over an entity's shape, the image is stretched
+/// over the box around the shape
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sprite {
+    // Path of the PNG to draw from
+    pub texture: String,
+    // The part of the image to draw from 0.0 to 1.0 from the top left, as
+    // left, top, right and bottom
+    pub region: [f32; 4],
+}
+
+impl Sprite {
+    /// Draw the whole of the PNG at `texture`
+    pub fn new(texture: &str) -> Self {
+        Self { texture: texture.to_string(), region: [0.0, 0.0, 1.0, 1.0] }
+    }
+}
+
+/// The named layers entities are drawn in, from the back to the front
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+pub enum Layer {
+    Background,
+    #[default]
+    World,
+    Foreground,
+    // Drawn over everything without the camera, so it stays in the same
+    // place on the screen
+    Ui,
+}
+
+/// Where an entity is drawn compared to others, layers are drawn in order
+/// and within a layer a higher z is drawn on top. Entities with the same
+/// depth are drawn in the order they were made
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Depth {
+    pub layer: Layer,
+    pub z: f32,
+}
+
+impl Depth {
+    /// Which of two depths is drawn first
+    pub fn order(&self, other: &Depth) -> std::cmp::Ordering {
+        self.layer.cmp(&other.layer).then(self.z.total_cmp(&other.z))
+    }
+}
+
+/// How an entity's colour is mixed with what is already drawn behind it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize)]
+pub enum BlendMode {
+    // Covers what is behind by its alpha, see-through where alpha is low
+    #[default]
+    Alpha,
+    // Adds its colour to what is behind so it only ever gets brighter, good
+    // for light and fire
+    Additive,
+    // Multiplies what is behind by its colour so it only ever gets darker,
+    // good for shadows and tinted glass
+    Multiply,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 3] =
+        [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply];
+}
+
+/// Attaches an entity to another so it moves, turns and scales with it. The
+/// entity's [Transform] is worked out from its parent's every tick, use
+/// [super::world::World::set_parent] to change it
+#[derive(Debug, Clone, Copy)]
+pub struct Parent {
+    pub id: EntityId,
+    // Where the entity is relative to its parent, in the parent's local
+    // space
+    pub local: Transform,
+}
+
+/// Tags an entity as controlled by the player
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Player {
+    // Ticks left that a jump pressed in the air will still happen when we
+    // land, so pressing jump just before landing is not ignored
+    pub jump_buffer: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Shape2D {
+        Shape2D::Polygon(Polygon::new(vec![
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(1.0, 1.0),
+            Point::new(0.0, 1.0),
+        ]))
+    }
+
+    #[test]
+    fn polygons_contain_points_inside_and_on_their_edges() {
+        let shape = square();
+        assert!(shape.contains(Point::new(0.5, 0.5)));
+        assert!(shape.contains(Point::new(1.0, 0.5)));
+        assert!(shape.contains(Point::new(0.0, 0.0)));
+        assert!(!shape.contains(Point::new(1.5, 0.5)));
+        assert!(!shape.contains(Point::new(-0.1, -0.1)));
+        // Clockwise points work the same
+        let clockwise = Shape2D::Polygon(Polygon::triangle(
+            Point::new(0.0, 0.0), Point::new(0.0, 1.0), Point::new(1.0, 0.0),
+        ));
+        assert!(clockwise.contains(Point::new(0.2, 0.2)));
+        assert!(!clockwise.contains(Point::new(0.8, 0.8)));
+    }
+
+    #[test]
+    fn circles_contain_points_within_their_radius() {
+        let circle = Shape2D::Circle(Circle::new(Point::new(1.0, 1.0), 0.5));
+        assert!(circle.contains(Point::new(1.0, 1.0)));
+        assert!(circle.contains(Point::new(1.5, 1.0)));
+        assert!(!circle.contains(Point::new(1.4, 1.4)));
+    }
+
+    /// Twice the area of the triangles `indices` makes from `points`, each
+    /// is positive if it goes anticlockwise
+    fn areas(points: &[Point], indices: &[u32]) -> Vec<f32> {
+        indices.chunks(3)
+            .map(|corner| {
+                let [a, b, c] = [0, 1, 2].map(|i| points[corner[i] as usize]);
+                (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn convex_polygons_are_split_into_triangles() {
+        let points = [
+            Point::new(0.0, 0.0),
+            Point::new(2.0, 0.0),
+            Point::new(2.0, 1.0),
+            Point::new(0.0, 1.0),
+        ];
+        let indices = triangulate(&points);
+        assert_eq!(indices.len(), 6);
+        let areas = areas(&points, &indices);
+        assert!(areas.iter().all(|area| *area > 0.0));
+        assert_eq!(areas.iter().sum::<f32>(), 4.0);
+    }
+
+    #[test]
+    fn concave_polygons_are_split_without_covering_the_gap() {
+        // An L shape going clockwise, its area is 3.0
+        let points = [
+            Point::new(0.0, 0.0),
+            Point::new(0.0, 2.0),
+            Point::new(1.0, 2.0),
+            Point::new(1.0, 1.0),
+            Point::new(2.0, 1.0),
+            Point::new(2.0, 0.0),
+        ];
+        let indices = triangulate(&points);
+        assert_eq!(indices.len(), 12);
+        // Every triangle goes the same way as the shape so none overlap
+        let areas = areas(&points, &indices);
+        assert!(areas.iter().all(|area| *area < 0.0), "{areas:?}");
+        assert_eq!(areas.iter().sum::<f32>(), -6.0);
+    }
+
+    fn close(a: &Transform, b: &Transform) -> bool {
+        (a.position - b.position).length() < 1e-5
+            && (a.rotation - b.rotation).abs() < 1e-5
+            && (a.scale - b.scale).length() < 1e-5
+            && (a.pivot - b.pivot).length() < 1e-5
+    }
+
+    /// Turned, stretched unevenly, mirrored and pivoting off its centre
+    fn transform() -> Transform {
+        Transform {
+            position: Point::new(1.0, -2.0),
+            rotation: 0.7,
+            scale: Point::new(2.0, -0.5),
+            pivot: Point::new(0.25, 0.5),
+        }
+    }
+
+    #[test]
+    fn transforms_turn_and_stretch_about_the_pivot() {
+        let transform = Transform {
+            position: Point::new(1.0, 0.0),
+            rotation: std::f32::consts::FRAC_PI_2,
+            scale: Point::new(2.0, 1.0),
+            pivot: Point::new(1.0, 0.0),
+        };
+        // The pivot only moves with the position
+        let pivot = transform.apply(Point::new(1.0, 0.0));
+        assert!((pivot - Point::new(2.0, 0.0)).length() < 1e-5);
+        // One along x is stretched to two then turned to point up
+        let point = transform.apply(Point::new(2.0, 0.0));
+        assert!((point - Point::new(2.0, 2.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn unapply_undoes_apply() {
+        let transform = transform();
+        for point in [Point::new(0.0, 0.0), Point::new(-3.0, 1.5)] {
+            let back = transform.unapply(transform.apply(point));
+            assert!((back - point).length() < 1e-5, "{point:?} is {back:?}");
         }
     }
+
+    #[test]
+    fn relative_undoes_combine() {
+        let parent = transform();
+        let local = Transform {
+            position: Point::new(0.5, 0.5),
+            rotation: -0.3,
+            scale: Point::new(1.5, 1.0),
+            pivot: Point::new(-0.1, 0.2),
+        };
+        let world = parent.combine(&local);
+        assert!(close(&parent.relative(&world), &local));
+        assert!(close(&parent.combine(&parent.relative(&world)), &world));
+        // An unmoved parent changes nothing
+        let origin = Transform::new(Point::new(0.0, 0.0));
+        assert!(close(&origin.combine(&local), &local));
+    }
+
+    #[test]
+    fn children_keep_their_place_on_the_parent() {
+        // A point on the child lands where the parent would put the point the
+        // child's own transform takes it to
+        let parent = transform();
+        let local = Transform::new(Point::new(1.0, 1.0));
+        let point = Point::new(0.5, -0.5);
+        let direct = parent.apply(local.apply(point));
+        let combined = parent.combine(&local).apply(point);
+        assert!((direct - combined).length() < 1e-5);
+    }
 }
\ No newline at end of file
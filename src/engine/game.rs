@@ -1,113 +1,459 @@
-use std::collections::HashMap;
-use winit::event::{KeyboardInput, ElementState, VirtualKeyCode};
-use super::entity::{Shape2D, Triangle, Rectangle, Point};
-use crate::globals::TICK_RATE;
+use std::collections::{HashMap, HashSet};
+use winit::event::{KeyboardInput, ElementState, MouseButton, VirtualKeyCode};
+use super::camera::Camera2D;
+use super::commands::{Commands, WorldEvent};
+use super::entity::{Layer, Point, Transform};
+use super::gamepad::{GamepadSource, GamepadState};
+use super::input::{Action, ActionStates, Binding, Bindings, MouseState};
+use super::level::{EntityDesc, Level, LevelError};
+use super::physics::State;
+use super::render::{Drawable, View};
+use super::world::{EntityId, Storage, World};
+use crate::globals::{
+    TICK_RATE, MAX_CATCHUP_TICKS, VIEW_HEIGHT, VIEW_WIDTH, ZOOM_STEP,
+};
 
 /// This will store our game state and pass it around
 #[derive(Debug)]
 pub struct Game {
-    // Stores all objects, we send this to the GPU for rendering
-    pub entities: Vec<Shape2D>,
-    // This will keep track of the player entities index
-    pub players: Vec<usize>,
-    // Keeps track of keys down
-    pub keys_down: HashMap<Option<VirtualKeyCode>, u8>,
+    // Stores all entities and their components
+    pub world: World,
+    // What part of the world we show, this follows the player
+    pub camera: Camera2D,
+    // The transforms as they were before the last tick, we render between
+    // these and the current ones so movement is smooth at any frame rate
+    previous_transforms: Storage<Transform>,
+    // Spawning, despawning and reparenting waiting for the end of the tick
+    commands: Commands,
+    // What happened to entities when the commands were last applied, these
+    // last until they are applied again at the end of the next tick
+    pub events: Vec<WorldEvent>,
+    // Keeps track of keys down and how many ticks they have been held
+    pub keys_down: HashMap<VirtualKeyCode, u32>,
+    // Keys pressed and released before a tick saw them, they count as down
+    // for one tick so quick taps are not lost
+    key_taps: HashSet<VirtualKeyCode>,
+    // Which actions were pressed, released or held this tick
+    pub actions: ActionStates,
+    // What is held down on any gamepad
+    pub gamepad: GamepadState,
+    // Which inputs trigger which actions
+    pub bindings: Bindings,
+    // Where the cursor is and what mouse buttons are held
+    pub mouse: MouseState,
+    // The size of the window in pixels, we need this to find where the
+    // cursor is in the world
+    window_size: (u32, u32),
     // Last time to calculate the delta
     last_time: std::time::Instant,
-    // Delta time to fix physics
+    // Time since the last frame that we have not run a tick for yet
+    accumulator: f32,
+    // How far we are between the previous and current tick, 0.0 to 1.0
+    pub alpha: f32,
+    // Delta time of a tick, this is always [TICK_RATE] unless we are stepped
     pub dt: f32,
 }
 
 impl Game {
-    /// Create a new game
+    /// Create a new game with the default level, see
+    /// `assets/levels/default.ron`
     pub fn new() -> Self {
-        // Init our arrays
-        let mut players: Vec<usize> = Vec::new();
-        let mut entities: Vec<Shape2D> = Vec::new();
-
-        // We push the index of our player into our players vec
-        players.push(entities.len());
-        // PLAYER
-        let player = Shape2D::Triangle(Triangle::new(
-            Point::new(-0.95, -0.9), // A
-            Point::new(-0.9,  -1.0), // B
-            Point::new(-1.0,  -1.0), // C
-            wgpu::Color::BLACK,
-            super::physics::Physics { state: super::physics::State::None, collides: false }
-        ));
-
-        entities.push(player);
-        //entities.push(Shape2D::Rectangle(Rectangle::default()));
-        //entities.push(Shape2D::Pentagon(Pentagon::default()));
-
-        let floor = Shape2D::Rectangle(
-            Rectangle::new(
-            Point::new(-1.1, -0.95),
-            Point::new(1.1, -0.95),
-            Point::new(-1.1, -1.05),
-            Point::new(1.1, -1.05),
-            wgpu::Color::GREEN,
-            super::physics::State::Static,
-        ));
-
-        let platform = Shape2D::Rectangle(
-            Rectangle::new(
-            Point::new(-0.7, -0.77),
-            Point::new(-0.5, -0.77),
-            Point::new(-0.7, -0.75),
-            Point::new(-0.5, -0.75),
-            wgpu::Color::GREEN,
-            super::physics::State::Static,
-        ));
-        
-        entities.push(floor);
-        entities.push(platform);
+        let level = Level::parse(include_str!("../../assets/levels/default.ron"))
+            .unwrap_or_else(|e| panic!("Bad default level: {e}"));
+        let mut world = World::new();
+        level.spawn(&mut world)
+            .unwrap_or_else(|e| panic!("Bad default level: {e}"));
 
         Self {
-            entities,
-            players,
+            camera: Self::player_camera(&world),
+            previous_transforms: world.transforms.clone(),
+            commands: Commands::default(),
+            events: Vec::new(),
+            world,
             keys_down: HashMap::new(),
+            key_taps: HashSet::new(),
+            actions: ActionStates::default(),
+            gamepad: GamepadState::default(),
+            bindings: Bindings::default(),
+            mouse: MouseState::default(),
+            window_size: (VIEW_WIDTH, VIEW_HEIGHT),
             last_time: std::time::Instant::now(),
-            dt: 0.0,
+            accumulator: 0.0,
+            alpha: 0.0,
+            dt: TICK_RATE,
+        }
+    }
+    /// Replace everything in the world with the level at `path`, if the
+    /// level is bad we keep the world we have
+    /// 
+    pub fn load_level(&mut self, path: &str) -> Result<(), LevelError> {
+        let level = Level::load(path)?;
+        // Despawn rather than start a new world so the generations go on
+        // and handles to the old entities never point at the new ones
+        let old = self.world.entities();
+        level.spawn(&mut self.world)?;
+        for id in old {
+            self.world.despawn(id);
+        }
+
+        // Keep how the player chose to fit the view on their screen
+        let scaling = self.camera.scaling;
+        self.camera = Self::player_camera(&self.world);
+        self.camera.scaling = scaling;
+        self.previous_transforms = self.world.transforms.clone();
+        // Anything queued was for the old world
+        self.commands = Commands::default();
+        self.events.clear();
+        Ok(())
+    }
+    /// A camera starting on the first player and following them, if there
+    /// are no players it sits at the origin
+    fn player_camera(world: &World) -> Camera2D {
+        let mut camera = Camera2D::default();
+        let player = world.players.iter().next()
+            .and_then(|(id, _)| Some((id, world.transforms.get(id)?)));
+        if let Some((id, transform)) = player {
+            camera.teleport(transform.position);
+            camera.follow(id);
         }
+        camera
     }
-    /// Update the delta to fix the rate at which the game is played
+    /// Save everything in the world as it is right now to `path`
     /// 
-    pub fn update_dt(&mut self){
+    pub fn save_level(&self, path: &str) -> Result<(), LevelError> {
+        Level::from_world(&self.world).save(path)
+    }
+    /// Run as many ticks of [TICK_RATE] as have passed since the last frame,
+    /// the time left over is kept for the next frame and turned into
+    /// [Self::alpha] so we can render between the last two ticks
+    /// 
+    pub fn advance(&mut self){
         // Get current time
         let current_time = std::time::Instant::now();
-        // Get the difference between the last frame and this one
-        self.dt = (current_time - self.last_time).as_secs_f32();
-        // If the game is running too fast we cap to 144 ticks per frame
-        if self.dt < TICK_RATE { 
-            self.dt = TICK_RATE;
-        }
+        // Add the difference between the last frame and this one
+        self.accumulator += (current_time - self.last_time).as_secs_f32();
         self.last_time = current_time;
+
+        let mut ticks = 0;
+        while self.accumulator >= TICK_RATE {
+            // If we are too far behind we drop the time rather than trying
+            // to catch up forever and making the next frame even slower
+            if ticks == MAX_CATCHUP_TICKS {
+                self.accumulator %= TICK_RATE;
+                break;
+            }
+            self.step(TICK_RATE);
+            self.accumulator -= TICK_RATE;
+            ticks += 1;
+        }
+        self.alpha = self.accumulator / TICK_RATE;
     }
     /// This is sent keyboard inputs from our event loop
     /// 
     pub fn keyboard_input(&mut self, input: &KeyboardInput){
         // This prevents a bug where we no longer get key events when we 
         // Press multiple at once, we add them to a dictionary that we trust
-        // as the truth of user inputs
-        match input.state {
+        // as the truth of user inputs. Some keys have no virtual keycode,
+        // they cant be bound to anything so we ignore them
+        if let Some(key) = input.virtual_keycode {
+            self.key_event(key, input.state);
+        }
+    }
+    /// Press or release a key, this is used by [Self::keyboard_input] and by
+    /// [super::headless] to script inputs without a window
+    /// 
+    pub fn key_event(&mut self, key: VirtualKeyCode, state: ElementState){
+        match state {
+            // Held keys repeat so we keep counting from the first press
             ElementState::Pressed => {
-                self.keys_down.insert(input.virtual_keycode, 0u8);
+                self.keys_down.entry(key).or_insert(0);
             }
             ElementState::Released => { 
-                self.keys_down.remove(&input.virtual_keycode);
+                if self.keys_down.remove(&key) == Some(0) {
+                    self.key_taps.insert(key);
+                }
+            },
+        }
+    }
+    /// The window is now `width` x `height` pixels
+    /// 
+    pub fn resize(&mut self, width: u32, height: u32){
+        self.window_size = (width, height);
+    }
+    /// The cursor moved to `position` in window pixels, or left the window
+    /// 
+    pub fn cursor_moved(&mut self, position: Option<Point>){
+        self.mouse.position = position;
+    }
+    /// Press or release a mouse button, pressing one returns the entity
+    /// that was clicked on if there is one, see [Self::pick]
+    /// 
+    pub fn mouse_button(&mut self, button: MouseButton, state: ElementState)
+        -> Option<EntityId>
+    {
+        match state {
+            ElementState::Pressed => {
+                self.mouse.buttons_down.insert(button);
+                self.pick()
             },
+            ElementState::Released => {
+                self.mouse.buttons_down.remove(&button);
+                None
+            },
+        }
+    }
+    /// Scroll the wheel by `lines`, up is positive and zooms in
+    /// 
+    pub fn mouse_wheel(&mut self, lines: f32){
+        self.mouse.wheel += lines;
+        self.camera.zoom *= ZOOM_STEP.powf(lines);
+    }
+    /// Where the cursor is in the world, None if it is outside the window or
+    /// in the bars around a letterboxed view
+    /// 
+    pub fn cursor_world(&self) -> Option<Point> {
+        let (width, height) = self.window_size;
+        self.view().projection(width, height)
+            .screen_to_world(self.mouse.position?)
+    }
+    /// Where the cursor is on the [Layer::Ui] layer, like
+    /// [Self::cursor_world] but without the camera
+    /// 
+    pub fn cursor_ui(&self) -> Option<Point> {
+        let (width, height) = self.window_size;
+        self.view().ui().projection(width, height)
+            .screen_to_world(self.mouse.position?)
+    }
+    /// The entity whose shape is under the cursor, if shapes overlap we take
+    /// the one drawn on top. Entities on the [Layer::Ui] layer are under the
+    /// cursor by where they are on the screen
+    /// 
+    pub fn pick(&self) -> Option<EntityId> {
+        let (cursor_world, cursor_ui) = (self.cursor_world(), self.cursor_ui());
+        let world = &self.world;
+        let is_static = |id| world.bodies.get(id)
+            .is_some_and(|body| body.state == State::Static);
+        world.shapes.join(&world.transforms)
+            .filter_map(|(id, shape, transform)| {
+                let depth = world.depths.get(id).copied().unwrap_or_default();
+                let point = match depth.layer {
+                    Layer::Ui => cursor_ui,
+                    _ => cursor_world,
+                }?;
+                shape.to_world(transform).contains(point).then_some((id, depth))
+            })
+            // The same order we draw in, at the same depth static entities
+            // go behind and the last one made is on top
+            .max_by(|(a, a_depth), (b, b_depth)| {
+                a_depth.order(b_depth).then(is_static(*b).cmp(&is_static(*a)))
+            })
+            .map(|(id, _)| id)
+    }
+    /// Read everything that has happened on the pads in `source`, the
+    /// window and [super::headless] call this before each frame or tick
+    /// 
+    pub fn poll_gamepad(&mut self, source: &mut dyn GamepadSource){
+        for event in source.poll() {
+            self.gamepad.apply(event);
         }
     }
+    /// How strongly `action` is held from 0.0 to 1.0, keys and buttons are
+    /// either 0.0 or 1.0 but sticks and triggers can be in between. If more
+    /// than one input is held we take the strongest
+    /// 
+    pub fn action_value(&self, action: Action) -> f32 {
+        let value = |binding: &Binding| match binding {
+            Binding::Key(key) => {
+                let down = self.keys_down.contains_key(key)
+                    || self.key_taps.contains(key);
+                if down { 1.0 } else { 0.0 }
+            },
+            Binding::Button(button) => {
+                if self.gamepad.button(*button) { 1.0 } else { 0.0 }
+            },
+            Binding::AxisPositive(axis) => self.gamepad.axis(*axis).max(0.0),
+            Binding::AxisNegative(axis) => (-self.gamepad.axis(*axis)).max(0.0),
+        };
+        self.bindings.inputs(action).iter().map(value).fold(0.0, f32::max)
+    }
+    /// Is any input bound to `action` held down
+    /// 
+    pub fn action_down(&self, action: Action) -> bool {
+        self.action_value(action) > 0.0
+    }
+    /// Run a single tick with a fixed `dt`, [Self::advance] calls this with
+    /// [TICK_RATE] and [super::headless] calls it directly
+    /// 
+    pub fn step(&mut self, dt: f32){
+        self.dt = dt;
+        self.previous_transforms.clone_from(&self.world.transforms);
+        self.update();
+    }
     /// Runs game logic in a tick, also calls physics and handles
     /// user input logic
     /// 
     pub fn update(&mut self){
+        // Take a snapshot of the inputs for this tick
+        self.update_actions();
         // Handle any user inputs
         super::controls::update(self);
         // Run the phsyics against our game
         super::physics::update(self);
+        // Carry attached entities along with what physics moved
+        self.world.propagate_transforms();
+        // Pick and move on animations now we know what physics did
+        super::animation::update(self);
         // Run the camera
         super::camera::update(self);
+        // Make the changes the systems asked for now they have all run
+        self.events.clear();
+        self.commands.apply(&mut self.world, &mut self.events);
+    }
+    /// Make an entity from `desc` at the end of the tick, see
+    /// [Commands::spawn]. The handle stays valid until the entity is
+    /// despawned
+    /// 
+    pub fn spawn(&mut self, desc: EntityDesc) -> Result<EntityId, String> {
+        self.commands.spawn(&mut self.world, desc)
+    }
+    /// Remove `id` and everything attached to it at the end of the tick
+    /// 
+    pub fn despawn(&mut self, id: EntityId) {
+        self.commands.despawn(id);
+    }
+    /// Attach `child` to `parent`, or to nothing, at the end of the tick. If
+    /// it cant be done a [WorldEvent::NotAttached] says why
+    /// 
+    pub fn set_parent(&mut self, child: EntityId, parent: Option<EntityId>) {
+        self.commands.set_parent(child, parent);
+    }
+    /// Count how long keys have been held and work out which actions were
+    /// pressed or released since the last tick
+    /// 
+    fn update_actions(&mut self){
+        for held in self.keys_down.values_mut() {
+            *held = held.saturating_add(1);
+        }
+        for action in Action::ALL {
+            let down = self.action_down(action);
+            self.actions.update(action, down);
+        }
+        self.key_taps.clear();
+    }
+    /// The shapes to draw this frame, moved to where they are between the
+    /// last two ticks by [Self::alpha]. If an entity did not exist last tick
+    /// we just draw it where it is
+    /// 
+    pub fn drawables(&self) -> Vec<Drawable> {
+        let world = &self.world;
+        world.shapes.join(&world.transforms)
+            .map(|(id, shape, transform)| {
+                let transform = match self.previous_transforms.get(id) {
+                    Some(previous) => transform.interpolate(previous, self.alpha),
+                    None => *transform,
+                };
+                Drawable {
+                    id,
+                    // Attached entities move with their parent
+                    is_static: world.parents.get(id).is_none()
+                        && world.bodies.get(id)
+                            .is_some_and(|body| body.state == State::Static),
+                    shape: shape.clone(),
+                    transform,
+                    depth: world.depths.get(id).copied().unwrap_or_default(),
+                    blend: world.blends.get(id).copied().unwrap_or_default(),
+                    colour: world.colours.get(id)
+                        .copied()
+                        .unwrap_or(wgpu::Color::BLACK),
+                    sprite: world.sprites.get(id).cloned(),
+                }
+            })
+            .collect()
+    }
+    /// How to look at the world this frame, see [Camera2D]
+    /// 
+    pub fn view(&self) -> View {
+        View {
+            view: self.camera.view(self.alpha),
+            scaling: self.camera.scaling,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloading_a_level_keeps_old_handles_dead() {
+        let mut game = Game::new();
+        let old = game.world.entities();
+        game.load_level("assets/levels/default.ron").unwrap();
+        let new = game.world.entities();
+        assert_eq!(old.len(), new.len());
+        assert!(old.iter().all(|id| !game.world.is_alive(*id)));
+        assert!(old.iter().all(|id| !new.contains(id)));
+        // The camera follows the new player
+        let player = game.world.players.iter().next().map(|(id, _)| id);
+        assert_eq!(game.camera.target, player);
+    }
+
+    #[test]
+    fn pressing_a_mouse_button_says_what_was_clicked() {
+        use crate::globals::{VIEW_HEIGHT, VIEW_WIDTH};
+        let mut game = Game::new();
+        // The camera starts on the player
+        let player = game.world.players.iter().next().map(|(id, _)| id);
+        let middle = Point::new(VIEW_WIDTH as f32, VIEW_HEIGHT as f32) * 0.5;
+        game.cursor_moved(Some(middle));
+        let (left, pressed) = (MouseButton::Left, ElementState::Pressed);
+        assert_eq!(game.mouse_button(left, pressed), player);
+        assert!(game.mouse.buttons_down.contains(&left));
+        assert_eq!(game.mouse_button(left, ElementState::Released), None);
+
+        game.cursor_moved(None);
+        assert_eq!(game.mouse_button(left, pressed), None);
+    }
+
+    #[test]
+    fn actions_count_ticks_held_by_any_input() {
+        use super::super::gamepad::{FakeGamepad, GamepadButton, GamepadEvent};
+        let mut game = Game::new();
+        let mut pad = FakeGamepad::default();
+        let dpad = |pressed| GamepadEvent::Button {
+            button: GamepadButton::DPadRight,
+            pressed,
+        };
+        fn step(game: &mut Game, pad: &mut FakeGamepad, ticks: u32) {
+            for _ in 0..ticks {
+                game.poll_gamepad(pad);
+                game.step(TICK_RATE);
+            }
+        }
+
+        game.key_event(VirtualKeyCode::D, ElementState::Pressed);
+        step(&mut game, &mut pad, 5);
+        assert_eq!(game.actions.held_ticks(Action::MoveRight), 5);
+
+        // Swapping to the pad part way through keeps counting
+        pad.push(dpad(true));
+        step(&mut game, &mut pad, 1);
+        game.key_event(VirtualKeyCode::D, ElementState::Released);
+        step(&mut game, &mut pad, 4);
+        assert_eq!(game.actions.held_ticks(Action::MoveRight), 10);
+        assert_eq!(game.actions.held_ticks(Action::MoveLeft), 0);
+
+        pad.push(dpad(false));
+        step(&mut game, &mut pad, 1);
+        assert_eq!(game.actions.held_ticks(Action::MoveRight), 0);
+        assert!(game.actions.just_released(Action::MoveRight));
+    }
+
+    #[test]
+    fn bad_levels_leave_the_world_alone() {
+        let mut game = Game::new();
+        let old = game.world.entities();
+        assert!(game.load_level("assets/levels/missing.ron").is_err());
+        assert_eq!(game.world.entities(), old);
     }
 }
\ No newline at end of file
@@ -0,0 +1,226 @@
+//! Gamepads feed the same [super::input] layer as the keyboard. Anything
+//! that can read a pad implements [GamepadSource] and gives us
+//! [GamepadEvent]s, so a [FakeGamepad] can stand in for a real one
+//!
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use super::entity::Point;
+use crate::globals::{STICK_DEAD_ZONE, TRIGGER_DEAD_ZONE};
+
+/// The buttons on a pad, named by where they are so they mean the same on
+/// every brand of pad. South is A on an Xbox pad and Cross on a PlayStation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// The analog inputs on a pad, sticks go from -1.0 to 1.0 with up and right
+/// being positive and triggers go from 0.0 to 1.0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// Something that changed on a pad
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GamepadEvent {
+    Button { button: GamepadButton, pressed: bool },
+    Axis { axis: GamepadAxis, value: f32 },
+}
+
+/// Anything we can read gamepad events from
+pub trait GamepadSource {
+    /// Take every event that has happened since we last asked
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+/// A pad that only does what it is told, used to script inputs
+#[derive(Debug, Default)]
+pub struct FakeGamepad {
+    events: Vec<GamepadEvent>,
+}
+
+impl FakeGamepad {
+    /// Queue `event` for the next [GamepadSource::poll]
+    pub fn push(&mut self, event: GamepadEvent) {
+        self.events.push(event);
+    }
+}
+
+impl GamepadSource for FakeGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// What is held down on the pads right now, we treat every pad as one so
+/// any of them can control the player
+#[derive(Debug, Default, Clone)]
+pub struct GamepadState {
+    buttons: HashSet<GamepadButton>,
+    // The raw values, dead zones are applied when we read them
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+impl GamepadState {
+    /// Update the state with something that happened on a pad
+    pub fn apply(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Button { button, pressed: true } => {
+                self.buttons.insert(button);
+            },
+            GamepadEvent::Button { button, pressed: false } => {
+                self.buttons.remove(&button);
+            },
+            GamepadEvent::Axis { axis, value } => {
+                self.axes.insert(axis, value);
+            },
+        }
+    }
+    /// Is `button` held down
+    pub fn button(&self, button: GamepadButton) -> bool {
+        self.buttons.contains(&button)
+    }
+    /// How far `axis` is pushed once the dead zones are taken out, small
+    /// movements from a worn stick or a resting finger read as 0.0
+    pub fn axis(&self, axis: GamepadAxis) -> f32 {
+        use GamepadAxis::*;
+        match axis {
+            LeftStickX  => self.stick(LeftStickX, LeftStickY).x,
+            LeftStickY  => self.stick(LeftStickX, LeftStickY).y,
+            RightStickX => self.stick(RightStickX, RightStickY).x,
+            RightStickY => self.stick(RightStickX, RightStickY).y,
+            LeftTrigger | RightTrigger => {
+                rescale(self.raw(axis).clamp(0.0, 1.0), TRIGGER_DEAD_ZONE)
+            },
+        }
+    }
+    fn raw(&self, axis: GamepadAxis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+    /// A stick with a round dead zone, we look at how far the stick is
+    /// pushed in any direction so diagonals are not cut off like they are
+    /// if each axis had its own dead zone
+    fn stick(&self, x: GamepadAxis, y: GamepadAxis) -> Point {
+        let stick = Point::new(
+            self.raw(x).clamp(-1.0, 1.0),
+            self.raw(y).clamp(-1.0, 1.0),
+        );
+        let length = stick.length().min(1.0);
+        stick.normalise() * rescale(length, STICK_DEAD_ZONE)
+    }
+}
+
+/// Take `dead_zone` off the bottom of a 0.0 to 1.0 `value` then stretch the
+/// rest back over 0.0 to 1.0 so there is no jump at the edge of the dead zone
+fn rescale(value: f32, dead_zone: f32) -> f32 {
+    if value <= dead_zone { return 0.0 }
+    ((value - dead_zone) / (1.0 - dead_zone)).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The state after sending `events` through a [FakeGamepad]
+    fn state(events: &[GamepadEvent]) -> GamepadState {
+        let mut pad = FakeGamepad::default();
+        for event in events {
+            pad.push(*event);
+        }
+        let mut state = GamepadState::default();
+        for event in pad.poll() {
+            state.apply(event);
+        }
+        state
+    }
+
+    fn axis(axis: GamepadAxis, value: f32) -> GamepadEvent {
+        GamepadEvent::Axis { axis, value }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn the_fake_pad_hands_over_each_event_once() {
+        let mut pad = FakeGamepad::default();
+        pad.push(axis(GamepadAxis::LeftStickX, 0.5));
+        assert_eq!(pad.poll().len(), 1);
+        assert!(pad.poll().is_empty());
+    }
+
+    #[test]
+    fn buttons_are_held_until_released() {
+        let press = |pressed| GamepadEvent::Button {
+            button: GamepadButton::South,
+            pressed,
+        };
+        assert!(state(&[press(true)]).button(GamepadButton::South));
+        assert!(!state(&[press(true), press(false)])
+            .button(GamepadButton::South));
+    }
+
+    #[test]
+    fn small_stick_movements_are_ignored() {
+        let pad = state(&[
+            axis(GamepadAxis::LeftStickX, STICK_DEAD_ZONE * 0.7),
+            axis(GamepadAxis::LeftStickY, STICK_DEAD_ZONE * 0.7),
+        ]);
+        assert_eq!(pad.axis(GamepadAxis::LeftStickX), 0.0);
+        assert_eq!(pad.axis(GamepadAxis::LeftStickY), 0.0);
+    }
+
+    #[test]
+    fn the_dead_zone_is_round() {
+        // Each axis alone is inside the dead zone but together they are not
+        let pad = state(&[
+            axis(GamepadAxis::RightStickX, STICK_DEAD_ZONE * 0.9),
+            axis(GamepadAxis::RightStickY, STICK_DEAD_ZONE * 0.9),
+        ]);
+        let x = pad.axis(GamepadAxis::RightStickX);
+        assert!(x > 0.0);
+        assert!(close(x, pad.axis(GamepadAxis::RightStickY)));
+    }
+
+    #[test]
+    fn sticks_are_rescaled_past_the_dead_zone() {
+        let value = |raw| state(&[axis(GamepadAxis::LeftStickX, raw)])
+            .axis(GamepadAxis::LeftStickX);
+        // Just past the dead zone is near 0.0, not a jump to the dead zone
+        assert!(value(STICK_DEAD_ZONE + 0.01) < 0.02);
+        let halfway = (1.0 + STICK_DEAD_ZONE) / 2.0;
+        assert!(close(value(halfway), 0.5));
+        assert!(close(value(-halfway), -0.5));
+        // Out of range values are clamped
+        assert!(close(value(1.0), 1.0));
+        assert!(close(value(3.0), 1.0));
+    }
+
+    #[test]
+    fn triggers_have_their_own_dead_zone() {
+        let value = |raw| state(&[axis(GamepadAxis::LeftTrigger, raw)])
+            .axis(GamepadAxis::LeftTrigger);
+        assert_eq!(value(TRIGGER_DEAD_ZONE), 0.0);
+        assert!(close(value((1.0 + TRIGGER_DEAD_ZONE) / 2.0), 0.5));
+        assert_eq!(value(-1.0), 0.0);
+        assert!(close(value(1.0), 1.0));
+    }
+}
@@ -0,0 +1,318 @@
+//! Runs a [Game] without a window or a GPU, we step the game with a fixed dt
+//! and feed it scripted inputs so we can simulate gameplay on CI machines
+//!
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+use super::Game;
+use super::entity::Point;
+use super::gamepad::{FakeGamepad, GamepadAxis, GamepadButton, GamepadEvent};
+
+/// An input that will be sent to the [Game] on a given tick
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptedInput {
+    pub tick: u32,
+    pub input: Scripted,
+}
+
+/// The inputs we can script
+#[derive(Debug, Clone, Copy)]
+pub enum Scripted {
+    Key { key: VirtualKeyCode, state: ElementState },
+    // Sent through a [FakeGamepad]
+    Gamepad(GamepadEvent),
+    // Window pixels from the top left, the window is the size the game
+    // starts out expecting
+    Cursor(Point),
+    Mouse { button: MouseButton, state: ElementState },
+    // Lines scrolled
+    Wheel(f32),
+}
+
+/// Steps a [Game] for a number of ticks with a fixed dt
+pub struct Headless {
+    game: Game,
+    dt: f32,
+    tick: u32,
+    script: Vec<ScriptedInput>,
+    // Scripted gamepad inputs go through here like a real pad would
+    gamepad: FakeGamepad,
+}
+
+impl Headless {
+    /// Create a new runner that will step `game` by `dt` every tick
+    pub fn new(game: Game, dt: f32) -> Self {
+        Self {
+            game,
+            dt,
+            tick: 0,
+            script: Vec::new(),
+            gamepad: FakeGamepad::default(),
+        }
+    }
+    /// Add inputs to the script, they dont need to be in order
+    pub fn script(&mut self, inputs: &[ScriptedInput]) {
+        self.script.extend_from_slice(inputs);
+        self.script.sort_by_key(|input| input.tick);
+    }
+    /// Run the game for `ticks` ticks, inputs scripted for a tick are sent
+    /// before that tick is updated
+    pub fn run(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            for input in self.script.iter().filter(|i| i.tick == self.tick) {
+                match input.input {
+                    Scripted::Key { key, state } => {
+                        self.game.key_event(key, state)
+                    },
+                    Scripted::Gamepad(event) => self.gamepad.push(event),
+                    Scripted::Cursor(position) => {
+                        self.game.cursor_moved(Some(position))
+                    },
+                    Scripted::Mouse { button, state } => {
+                        self.game.mouse_button(button, state);
+                    },
+                    Scripted::Wheel(lines) => self.game.mouse_wheel(lines),
+                }
+            }
+            self.game.poll_gamepad(&mut self.gamepad);
+            self.game.step(self.dt);
+            self.tick += 1;
+        }
+    }
+    /// The game we are running, used to inspect the state after [Self::run]
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+}
+
+/// Parse a script of inputs, one per line in the form `<tick> <press|release>
+/// <key or button>`, `<tick> axis <axis> <value>`, `<tick> cursor <x> <y>`
+/// or `<tick> wheel <lines>`, blank lines and lines starting with `#` are
+/// ignored. Mouse buttons are `MouseLeft`, `MouseRight` and `MouseMiddle`
+///
+/// ```text
+/// # Jump on the first tick then walk right for a second
+/// 0   press   Space
+/// 1   release Space
+/// 0   press   D
+/// 144 release D
+/// # Then walk back at half speed with a gamepad
+/// 144 axis    LeftStickX -0.6
+/// 288 axis    LeftStickX 0.0
+/// # Click on the middle of the screen
+/// 300 cursor  400 300
+/// 300 press   MouseLeft
+/// ```
+pub fn parse_script(script: &str) -> Result<Vec<ScriptedInput>, String> {
+    let mut inputs = Vec::new();
+    for (line_no, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue }
+
+        let error = |msg: &str| format!("line {}: {msg}: {line:?}", line_no + 1);
+        let mut parts = line.split_whitespace();
+
+        let tick = parts.next()
+            .and_then(|tick| tick.parse().ok())
+            .ok_or_else(|| error("expected a tick number"))?;
+        let state = match parts.next() {
+            Some("press")   => ElementState::Pressed,
+            Some("release") => ElementState::Released,
+            Some("axis") => {
+                let axis = parts.next()
+                    .and_then(parse_axis)
+                    .ok_or_else(|| error("unknown axis"))?;
+                let value = parts.next()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| error("expected an axis value"))?;
+                if parts.next().is_some() {
+                    return Err(error("unexpected trailing input"))
+                }
+                let event = GamepadEvent::Axis { axis, value };
+                inputs.push(ScriptedInput { tick, input: Scripted::Gamepad(event) });
+                continue
+            },
+            Some("cursor") => {
+                let mut number = || parts.next().and_then(|n| n.parse().ok());
+                let (x, y) = number().zip(number())
+                    .ok_or_else(|| error("expected an x and y"))?;
+                if parts.next().is_some() {
+                    return Err(error("unexpected trailing input"))
+                }
+                let input = Scripted::Cursor(Point::new(x, y));
+                inputs.push(ScriptedInput { tick, input });
+                continue
+            },
+            Some("wheel") => {
+                let lines = parts.next()
+                    .and_then(|lines| lines.parse().ok())
+                    .ok_or_else(|| error("expected a number of lines"))?;
+                if parts.next().is_some() {
+                    return Err(error("unexpected trailing input"))
+                }
+                inputs.push(ScriptedInput { tick, input: Scripted::Wheel(lines) });
+                continue
+            },
+            _ => return Err(error(
+                "expected `press`, `release`, `axis`, `cursor` or `wheel`"
+            )),
+        };
+        let name = parts.next().unwrap_or_default();
+        let input = if let Some(key) = parse_key(name) {
+            Scripted::Key { key, state }
+        } else if let Some(button) = parse_button(name) {
+            let pressed = state == ElementState::Pressed;
+            Scripted::Gamepad(GamepadEvent::Button { button, pressed })
+        } else if let Some(button) = parse_mouse_button(name) {
+            Scripted::Mouse { button, state }
+        } else {
+            return Err(error("unknown key or button"))
+        };
+        if parts.next().is_some() {
+            return Err(error("unexpected trailing input"))
+        }
+        inputs.push(ScriptedInput { tick, input });
+    }
+    Ok(inputs)
+}
+
+/// Turn the name of a key into a [VirtualKeyCode], we only know about the
+/// keys that we are likely to script
+pub fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    use VirtualKeyCode::*;
+    let key = match name {
+        "A" => A, "B" => B, "C" => C, "D" => D, "E" => E, "F" => F, "G" => G,
+        "H" => H, "I" => I, "J" => J, "K" => K, "L" => L, "M" => M, "N" => N,
+        "O" => O, "P" => P, "Q" => Q, "R" => R, "S" => S, "T" => T, "U" => U,
+        "V" => V, "W" => W, "X" => X, "Y" => Y, "Z" => Z,
+        "Key0" => Key0, "Key1" => Key1, "Key2" => Key2, "Key3" => Key3,
+        "Key4" => Key4, "Key5" => Key5, "Key6" => Key6, "Key7" => Key7,
+        "Key8" => Key8, "Key9" => Key9,
+        "Up" => Up, "Down" => Down, "Left" => Left, "Right" => Right,
+        "Space" => Space, "Return" => Return, "Escape" => Escape, "Tab" => Tab,
+        "Back" => Back, "LShift" => LShift, "RShift" => RShift,
+        "LControl" => LControl, "RControl" => RControl,
+        "LAlt" => LAlt, "RAlt" => RAlt,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Turn the name of a gamepad button into a [GamepadButton]
+pub fn parse_button(name: &str) -> Option<GamepadButton> {
+    use GamepadButton::*;
+    let button = match name {
+        "South" => South, "East" => East, "West" => West, "North" => North,
+        "LeftShoulder" => LeftShoulder, "RightShoulder" => RightShoulder,
+        "Select" => Select, "Start" => Start,
+        "DPadUp" => DPadUp, "DPadDown" => DPadDown,
+        "DPadLeft" => DPadLeft, "DPadRight" => DPadRight,
+        _ => return None,
+    };
+    Some(button)
+}
+
+/// Turn the name of a mouse button into a [MouseButton]
+pub fn parse_mouse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "MouseLeft"   => Some(MouseButton::Left),
+        "MouseRight"  => Some(MouseButton::Right),
+        "MouseMiddle" => Some(MouseButton::Middle),
+        _ => None,
+    }
+}
+
+/// Turn the name of a gamepad axis into a [GamepadAxis]
+pub fn parse_axis(name: &str) -> Option<GamepadAxis> {
+    use GamepadAxis::*;
+    let axis = match name {
+        "LeftStickX" => LeftStickX, "LeftStickY" => LeftStickY,
+        "RightStickX" => RightStickX, "RightStickY" => RightStickY,
+        "LeftTrigger" => LeftTrigger, "RightTrigger" => RightTrigger,
+        _ => return None,
+    };
+    Some(axis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::world::EntityId;
+    use crate::globals::TICK_RATE;
+
+    #[test]
+    fn parses_every_kind_of_input() {
+        let script = "\
+            # A comment and a blank line\n\
+            \n\
+            0   press   Space\n\
+            1   release D\n\
+            2   press   South\n\
+            3   axis    LeftStickX -0.5\n\
+            4   cursor  400 300\n\
+            5   press   MouseLeft\n\
+            6   wheel   -2\n";
+        let inputs = parse_script(script).unwrap();
+        assert_eq!(inputs.len(), 7);
+        assert!(matches!(inputs[0], ScriptedInput {
+            tick: 0,
+            input: Scripted::Key {
+                key: VirtualKeyCode::Space,
+                state: ElementState::Pressed,
+            },
+        }));
+        assert!(matches!(inputs[1].input, Scripted::Key {
+            key: VirtualKeyCode::D,
+            state: ElementState::Released,
+        }));
+        assert!(matches!(inputs[2].input, Scripted::Gamepad(
+            GamepadEvent::Button { button: GamepadButton::South, pressed: true }
+        )));
+        assert!(matches!(inputs[3].input, Scripted::Gamepad(
+            GamepadEvent::Axis { axis: GamepadAxis::LeftStickX, value }
+        ) if value == -0.5));
+        assert!(matches!(inputs[4].input, Scripted::Cursor(p)
+            if p == Point::new(400.0, 300.0)));
+        assert!(matches!(inputs[5].input, Scripted::Mouse {
+            button: MouseButton::Left,
+            state: ElementState::Pressed,
+        }));
+        assert!(matches!(inputs[6].input, Scripted::Wheel(lines)
+            if lines == -2.0));
+    }
+
+    #[test]
+    fn bad_lines_say_where_they_are() {
+        for (script, message) in [
+            ("press Space", "line 1: expected a tick number"),
+            ("0 hold Space", "line 1: expected `press`"),
+            ("\n0 press Banana", "line 2: unknown key or button"),
+            ("0 press Space now", "line 1: unexpected trailing input"),
+            ("0 axis Wheel 1.0", "line 1: unknown axis"),
+            ("0 axis LeftStickX", "line 1: expected an axis value"),
+            ("0 cursor 10", "line 1: expected an x and y"),
+            ("0 wheel up", "line 1: expected a number of lines"),
+        ] {
+            let error = parse_script(script).unwrap_err();
+            assert!(error.starts_with(message), "{script:?} gave {error:?}");
+        }
+    }
+
+    /// Where every entity is after running `script` for `ticks`
+    fn run(script: &str, ticks: u32) -> Vec<(EntityId, Point)> {
+        let mut runner = Headless::new(Game::new(), TICK_RATE);
+        runner.script(&parse_script(script).unwrap());
+        runner.run(ticks);
+        runner.game().world.transforms.iter()
+            .map(|(id, transform)| (id, transform.position))
+            .collect()
+    }
+
+    #[test]
+    fn runs_are_the_same_every_time() {
+        let script = "0 press D\n30 press Space\n31 release Space\n\
+            90 release D\n90 axis LeftStickX -1.0\n150 axis LeftStickX 0.0";
+        let first = run(script, 200);
+        assert_eq!(first, run(script, 200));
+        // And the script really did something
+        assert_ne!(first, run("", 200));
+    }
+}
@@ -0,0 +1,265 @@
+//! Here we turn raw inputs like keys and gamepad buttons into [Action]s, the
+//! game only ever asks about actions so the player can change which inputs
+//! do what
+//!
+use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use winit::event::{MouseButton, VirtualKeyCode};
+use super::entity::Point;
+use super::gamepad::{GamepadAxis, GamepadButton};
+
+/// Something the player can do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+}
+
+impl Action {
+    /// Every action, so we can check them all each tick
+    pub const ALL: [Action; 3] = [Action::MoveLeft, Action::MoveRight, Action::Jump];
+}
+
+/// An input that can trigger an [Action]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Button(GamepadButton),
+    // Pushing the axis above 0.0, such as right on a stick
+    AxisPositive(GamepadAxis),
+    // Pushing the axis below 0.0, such as left on a stick
+    AxisNegative(GamepadAxis),
+}
+
+impl std::fmt::Display for Binding {
+    /// The name of the key, button or axis, with the way axes are pushed
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{key:?}"),
+            Binding::Button(button) => write!(f, "{button:?}"),
+            Binding::AxisPositive(axis) => write!(f, "{axis:?}+"),
+            Binding::AxisNegative(axis) => write!(f, "{axis:?}-"),
+        }
+    }
+}
+
+/// Which inputs trigger each [Action], an action can have any number of
+/// inputs but an input only triggers one action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    // Sorted so saved bindings are always in the same order
+    actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self { actions: BTreeMap::new() };
+        for (action, binding) in [
+            (Action::MoveLeft,  Binding::Key(VirtualKeyCode::A)),
+            (Action::MoveLeft,  Binding::Key(VirtualKeyCode::Left)),
+            (Action::MoveLeft,  Binding::Button(GamepadButton::DPadLeft)),
+            (Action::MoveLeft,  Binding::AxisNegative(GamepadAxis::LeftStickX)),
+            (Action::MoveRight, Binding::Key(VirtualKeyCode::D)),
+            (Action::MoveRight, Binding::Key(VirtualKeyCode::Right)),
+            (Action::MoveRight, Binding::Button(GamepadButton::DPadRight)),
+            (Action::MoveRight, Binding::AxisPositive(GamepadAxis::LeftStickX)),
+            (Action::Jump,      Binding::Key(VirtualKeyCode::W)),
+            (Action::Jump,      Binding::Key(VirtualKeyCode::Space)),
+            (Action::Jump,      Binding::Button(GamepadButton::South)),
+            (Action::Jump,      Binding::AxisPositive(GamepadAxis::RightTrigger)),
+        ] {
+            bindings.bind(action, binding);
+        }
+        bindings
+    }
+}
+
+impl Bindings {
+    /// Make `binding` trigger `action`, if it triggered another action it
+    /// stops doing that
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.unbind(binding);
+        self.actions.entry(action).or_default().push(binding);
+    }
+    /// Stop `binding` triggering anything
+    pub fn unbind(&mut self, binding: Binding) {
+        for bindings in self.actions.values_mut() {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+    /// The inputs that trigger `action`
+    pub fn inputs(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], Vec::as_slice)
+    }
+    /// Read bindings from a RON file, see [Self::parse]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+    /// Read bindings from RON, for example
+    ///
+    /// ```text
+    /// (actions: {
+    ///     MoveLeft: [Key(A), Key(Left), AxisNegative(LeftStickX)],
+    ///     MoveRight: [Key(D), Key(Right), AxisPositive(LeftStickX)],
+    ///     Jump: [Key(Space), Button(South)],
+    /// })
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bindings: Bindings = ron::from_str(text).map_err(|e| e.to_string())?;
+
+        // An input can only trigger one action
+        let mut seen: HashMap<Binding, Action> = HashMap::new();
+        for (action, inputs) in &bindings.actions {
+            for binding in inputs {
+                if let Some(other) = seen.insert(*binding, *action) {
+                    return Err(format!(
+                        "{binding:?} is bound to both {other:?} and {action:?}"
+                    ))
+                }
+            }
+        }
+        Ok(bindings)
+    }
+    /// Write the bindings to a RON file that [Self::load] can read
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(
+            self, ron::ser::PrettyConfig::default()
+        ).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+}
+
+/// How one action has changed, see [ActionStates]
+#[derive(Debug, Default, Clone, Copy)]
+struct ActionState {
+    // How many ticks in a row any of its inputs has been held, 0 when they
+    // are all up
+    held_ticks: u32,
+    just_pressed: bool,
+    just_released: bool,
+}
+
+/// A snapshot of every [Action] taken at the start of each tick, this lets
+/// us tell an action that was pressed this tick from one that is held
+#[derive(Debug, Default, Clone)]
+pub struct ActionStates {
+    states: HashMap<Action, ActionState>,
+}
+
+impl ActionStates {
+    /// Record if `action` is `down` this tick
+    pub fn update(&mut self, action: Action, down: bool) {
+        let state = self.states.entry(action).or_default();
+        let was_down = state.held_ticks > 0;
+        state.just_pressed = down && !was_down;
+        state.just_released = !down && was_down;
+        state.held_ticks = match down {
+            true => state.held_ticks.saturating_add(1),
+            false => 0,
+        };
+    }
+    /// Was `action` up last tick and down this tick
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.states.get(&action).is_some_and(|s| s.just_pressed)
+    }
+    /// Was `action` down last tick and up this tick
+    pub fn just_released(&self, action: Action) -> bool {
+        self.states.get(&action).is_some_and(|s| s.just_released)
+    }
+    /// How many ticks in a row `action` has been held by any of its inputs,
+    /// 0 if it is up
+    pub fn held_ticks(&self, action: Action) -> u32 {
+        self.states.get(&action).map_or(0, |s| s.held_ticks)
+    }
+}
+
+/// What the mouse is doing, the position is in window pixels from the top
+/// left, see [super::Game::cursor_world] for where that is in the world
+#[derive(Debug, Default, Clone)]
+pub struct MouseState {
+    // None when the cursor is outside the window
+    pub position: Option<Point>,
+    pub buttons_down: HashSet<MouseButton>,
+    // Lines scrolled since the game started, up is positive
+    pub wheel: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_are_read_from_ron() {
+        let bindings = Bindings::parse("(actions: {
+            MoveLeft: [Key(A), AxisNegative(LeftStickX)],
+            Jump: [Button(South)],
+        })").unwrap();
+        assert_eq!(bindings.inputs(Action::MoveLeft), [
+            Binding::Key(VirtualKeyCode::A),
+            Binding::AxisNegative(GamepadAxis::LeftStickX),
+        ]);
+        assert_eq!(bindings.inputs(Action::Jump), [
+            Binding::Button(GamepadButton::South),
+        ]);
+        assert!(bindings.inputs(Action::MoveRight).is_empty());
+    }
+
+    #[test]
+    fn bindings_are_named_for_the_hud() {
+        let names: Vec<String> = Bindings::default()
+            .inputs(Action::MoveLeft)
+            .iter()
+            .map(Binding::to_string)
+            .collect();
+        assert_eq!(names, ["A", "Left", "DPadLeft", "LeftStickX-"]);
+    }
+
+    #[test]
+    fn an_input_cant_trigger_two_actions() {
+        let error = Bindings::parse("(actions: {
+            MoveLeft: [Key(A)],
+            Jump: [Key(Space), Key(A)],
+        })").unwrap_err();
+        assert_eq!(error, "Key(A) is bound to both MoveLeft and Jump");
+    }
+
+    #[test]
+    fn binding_an_input_again_moves_it() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Jump, Binding::Key(VirtualKeyCode::A));
+        assert!(!bindings.inputs(Action::MoveLeft)
+            .contains(&Binding::Key(VirtualKeyCode::A)));
+        assert!(bindings.inputs(Action::Jump)
+            .contains(&Binding::Key(VirtualKeyCode::A)));
+    }
+
+    #[test]
+    fn presses_and_releases_only_last_one_tick() {
+        let mut actions = ActionStates::default();
+        assert!(!actions.just_pressed(Action::Jump));
+        let mut ticks = Vec::new();
+        for down in [true, true, false, false, true] {
+            actions.update(Action::Jump, down);
+            ticks.push((
+                actions.just_pressed(Action::Jump),
+                actions.just_released(Action::Jump),
+            ));
+        }
+        assert_eq!(ticks, [
+            (true, false),
+            (false, false),
+            (false, true),
+            (false, false),
+            (true, false),
+        ]);
+        assert_eq!(actions.held_ticks(Action::Jump), 1);
+        actions.update(Action::Jump, true);
+        assert_eq!(actions.held_ticks(Action::Jump), 2);
+        // Other actions are not affected
+        assert!(!actions.just_pressed(Action::MoveLeft));
+        assert_eq!(actions.held_ticks(Action::MoveLeft), 0);
+    }
+}
@@ -0,0 +1,639 @@
+//! Levels are described in RON files so they can be changed without
+//! recompiling, see `assets/levels/default.ron` for an example. Every shape
+//! in a level file is given in world space as it is before it is rotated or
+//! scaled
+//!
+use std::collections::HashMap;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use super::animation::{Animator, SpriteSheet};
+use super::entity::{
+    BlendMode, Circle, Depth, Layer, Player, Point, Polygon, Shape2D, Sprite,
+    Transform,
+};
+use super::physics::{Physics, RigidBody, State};
+use super::world::{EntityId, World};
+
+/// Everything we need to build a [World]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Level {
+    pub entities: Vec<EntityDesc>,
+}
+
+/// One entity in a [Level]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntityDesc {
+    // Only used to make errors easier to find
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub shape: ShapeDesc,
+    // Red, green, blue, alpha from 0.0 to 1.0
+    pub colour: [f64; 4],
+    #[serde(default = "default_state")]
+    pub state: State,
+    #[serde(default = "default_collides")]
+    pub collides: bool,
+    #[serde(default)]
+    pub body: RigidBody,
+    #[serde(default)]
+    pub player: bool,
+    // Path of a PNG to draw over the shape, tinted by the colour
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sprite: Option<String>,
+    // Path of a sprite sheet RON to animate the shape with, the sheet says
+    // which PNG to draw so this cant be used with a sprite
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<String>,
+    // Which layer it is drawn in and where in that layer, see [Depth]
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub layer: Layer,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub z: f32,
+    // How its colour is mixed with what is behind it
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub blend: BlendMode,
+    // Anticlockwise in degrees around the pivot
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub rotation: f32,
+    // How much the shape is stretched along its own x and y around the pivot
+    #[serde(default = "default_scale", skip_serializing_if = "is_unscaled")]
+    pub scale: Point,
+    // What the shape turns and stretches around in world space, the middle
+    // of the shape if there is none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pivot: Option<Point>,
+    // Where in the entities list the entity this is attached to is, it
+    // moves, turns and scales with that entity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+}
+
+fn default_state() -> State { State::Static }
+fn default_collides() -> bool { true }
+fn default_scale() -> Point { Point::new(1.0, 1.0) }
+fn is_unscaled(scale: &Point) -> bool { *scale == default_scale() }
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// The shapes we can describe in a level, these are turned into [Shape2D]s
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ShapeDesc {
+    // Any convex polygon, the points go round the outside in order
+    Polygon(Vec<Point>),
+    Triangle(Point, Point, Point),
+    Rectangle { min: Point, max: Point },
+    Pentagon { centre: Point, radius: f32 },
+    Circle { centre: Point, radius: f32 },
+}
+
+/// Why we could not load or save a level
+#[derive(Debug)]
+pub enum LevelError {
+    // We could not read or write the file
+    Io(String),
+    // The file is not valid RON or does not match a [Level]
+    Parse { line: usize, col: usize, message: String },
+    // An entity in the level makes no sense, `index` is its position in the
+    // entities list
+    Entity { index: usize, name: Option<String>, message: String },
+}
+
+impl std::fmt::Display for LevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LevelError::Io(message) => write!(f, "{message}"),
+            LevelError::Parse { line, col, message } => {
+                write!(f, "line {line}, column {col}: {message}")
+            },
+            LevelError::Entity { index, name: Some(name), message } => {
+                write!(f, "entity {index} ({name:?}): {message}")
+            },
+            LevelError::Entity { index, name: None, message } => {
+                write!(f, "entity {index}: {message}")
+            },
+        }
+    }
+}
+
+impl Level {
+    /// Read and check a level file
+    pub fn load(path: &str) -> Result<Self, LevelError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| LevelError::Io(e.to_string()))?;
+        Self::parse(&text)
+    }
+    /// Write the level to a file
+    pub fn save(&self, path: &str) -> Result<(), LevelError> {
+        let text = ron::ser::to_string_pretty(
+            self, ron::ser::PrettyConfig::default()
+        ).map_err(|e| LevelError::Io(e.to_string()))?;
+        std::fs::write(path, text)
+            .map_err(|e| LevelError::Io(e.to_string()))
+    }
+    /// Parse and check a level from RON
+    pub fn parse(text: &str) -> Result<Self, LevelError> {
+        let mut deserializer = ron::Deserializer::from_str(text)
+            .map_err(|e| parse_error(text, text, e))?;
+        let level = Level::deserialize(&mut deserializer)
+            .and_then(|level| deserializer.end().map(|_| level))
+            .map_err(|e| parse_error(text, &deserializer.remainder(), e))?;
+        level.validate()?;
+        Ok(level)
+    }
+    /// Check every entity makes sense before we build anything from it
+    pub fn validate(&self) -> Result<(), LevelError> {
+        for (index, entity) in self.entities.iter().enumerate() {
+            entity.validate()
+                .and_then(|_| self.validate_parent(index))
+                .map_err(|message| LevelError::Entity {
+                    index,
+                    name: entity.name.clone(),
+                    message,
+                })?;
+        }
+        Ok(())
+    }
+    /// Check the entity at `index` is attached to an entity that exists and
+    /// that following its parents never comes back round to it
+    fn validate_parent(&self, index: usize) -> Result<(), String> {
+        let mut parent = self.entities[index].parent;
+        // A chain longer than the list must go round in a loop
+        for _ in 0..self.entities.len() {
+            let next = match parent {
+                Some(next) => next,
+                None => return Ok(()),
+            };
+            if next >= self.entities.len() {
+                return Err(format!(
+                    "parent {next} is not in the list of {} entities",
+                    self.entities.len()
+                ))
+            }
+            if next == index {
+                return Err("entity is attached to itself through its \
+                    parents".into())
+            }
+            parent = self.entities[next].parent;
+        }
+        Ok(())
+    }
+    /// Add every entity in the level to `world`, this fails if a sprite
+    /// sheet cant be loaded and then nothing is added
+    pub fn spawn(&self, world: &mut World) -> Result<(), LevelError> {
+        // Entities using the same sheet share it
+        let mut sheets = HashMap::new();
+        let sheets = self.entities.iter()
+            .enumerate()
+            .map(|(index, entity)| {
+                entity.load_sheet(&mut sheets)
+                    .map_err(|message| LevelError::Entity {
+                        index,
+                        name: entity.name.clone(),
+                        message,
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut ids = Vec::with_capacity(self.entities.len());
+        for (entity, sheet) in self.entities.iter().zip(sheets) {
+            let id = world.spawn();
+            entity.insert(world, id, sheet);
+            ids.push(id);
+        }
+
+        // Attach entities once they all exist, they stay where the level
+        // put them
+        for (index, entity) in self.entities.iter().enumerate() {
+            let parent = match entity.parent {
+                Some(parent) => parent,
+                None => continue,
+            };
+            world.set_parent(ids[index], Some(ids[parent]))
+                .map_err(|message| LevelError::Entity {
+                    index,
+                    name: entity.name.clone(),
+                    message,
+                })?;
+        }
+        Ok(())
+    }
+    /// Describe everything in `world` that has a shape as it is right now
+    pub fn from_world(world: &World) -> Self {
+        // Where each entity will be in the list, so children can say which
+        // is their parent
+        let indices: HashMap<EntityId, usize> = world.shapes
+            .join(&world.transforms)
+            .enumerate()
+            .map(|(index, (id, _, _))| (id, index))
+            .collect();
+        let entities = world.shapes.join(&world.transforms)
+            .map(|(id, shape, transform)| {
+                let physics = world.bodies.get(id).copied().unwrap_or_default();
+                let colour = world.colours.get(id)
+                    .copied()
+                    .unwrap_or(wgpu::Color::BLACK);
+                let animator = world.animators.get(id);
+                let depth = world.depths.get(id).copied().unwrap_or_default();
+                EntityDesc {
+                    name: world.names.get(id).cloned(),
+                    shape: ShapeDesc::from_shape(
+                        &shape.translated(transform.position)
+                    ),
+                    colour: [colour.r, colour.g, colour.b, colour.a],
+                    state: physics.state,
+                    collides: physics.collides,
+                    body: physics.body,
+                    player: world.players.get(id).is_some(),
+                    // The sprite of an animated entity comes from its sheet
+                    sprite: match animator {
+                        Some(_) => None,
+                        None => world.sprites.get(id)
+                            .map(|sprite| sprite.texture.clone()),
+                    },
+                    animation: animator
+                        .map(|animator| animator.sheet.path.clone()),
+                    layer: depth.layer,
+                    z: depth.z,
+                    blend: world.blends.get(id).copied().unwrap_or_default(),
+                    // In f64 so whole degrees come back whole
+                    rotation: (transform.rotation as f64).to_degrees() as f32,
+                    scale: transform.scale,
+                    pivot: (transform.pivot != Point::new(0.0, 0.0))
+                        .then(|| transform.position + transform.pivot),
+                    parent: world.parents.get(id)
+                        .and_then(|parent| indices.get(&parent.id))
+                        .copied(),
+                }
+            })
+            .collect();
+        Self { entities }
+    }
+}
+
+impl EntityDesc {
+    /// A static entity with just a shape and colour, everything else is
+    /// what a level file gets if it leaves it out
+    pub fn new(shape: ShapeDesc, colour: [f64; 4]) -> Self {
+        Self {
+            name: None,
+            shape,
+            colour,
+            state: default_state(),
+            collides: default_collides(),
+            body: RigidBody::default(),
+            player: false,
+            sprite: None,
+            animation: None,
+            layer: Layer::default(),
+            z: 0.0,
+            blend: BlendMode::default(),
+            rotation: 0.0,
+            scale: default_scale(),
+            pivot: None,
+            parent: None,
+        }
+    }
+    /// Load the sprite sheet the entity is animated with if it has one,
+    /// sheets already in `sheets` are shared rather than loaded again
+    pub fn load_sheet(&self, sheets: &mut HashMap<String, Rc<SpriteSheet>>)
+        -> Result<Option<Rc<SpriteSheet>>, String>
+    {
+        let path = match &self.animation {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if let Some(sheet) = sheets.get(path) {
+            return Ok(Some(Rc::clone(sheet)))
+        }
+        let sheet = SpriteSheet::load(path)
+            .map(Rc::new)
+            .map_err(|e| format!("sprite sheet {path}: {e}"))?;
+        sheets.insert(path.clone(), Rc::clone(&sheet));
+        Ok(Some(sheet))
+    }
+    /// Give `id` the components the entity is made of, `sheet` is what
+    /// [Self::load_sheet] loaded. Its parent is left to whoever knows which
+    /// entity that is
+    pub fn insert(
+        &self,
+        world: &mut World,
+        id: EntityId,
+        sheet: Option<Rc<SpriteSheet>>,
+    ) {
+        let [r, g, b, a] = self.colour;
+        let physics = Physics::new(self.state, self.collides)
+            .with_body(self.body);
+
+        world.insert_shape(
+            id,
+            self.shape.to_shape(),
+            wgpu::Color { r, g, b, a },
+            physics,
+        );
+        if let Some(transform) = world.transforms.get_mut(id) {
+            let position = transform.position;
+            *transform = Transform {
+                rotation: self.rotation.to_radians(),
+                scale: self.scale,
+                pivot: self.pivot
+                    .map_or(Point::new(0.0, 0.0), |pivot| pivot - position),
+                ..*transform
+            };
+        }
+        if self.player {
+            world.players.insert(id, Player::default());
+        }
+        if let Some(name) = &self.name {
+            world.names.insert(id, name.clone());
+        }
+        let depth = Depth { layer: self.layer, z: self.z };
+        if depth != Depth::default() {
+            world.depths.insert(id, depth);
+        }
+        if self.blend != BlendMode::default() {
+            world.blends.insert(id, self.blend);
+        }
+        if let Some(texture) = &self.sprite {
+            world.sprites.insert(id, Sprite::new(texture));
+        }
+        if let Some(sheet) = sheet {
+            let animator = Animator::new(sheet);
+            world.sprites.insert(id, Sprite {
+                texture: animator.sheet.texture.clone(),
+                region: animator.region(),
+            });
+            world.animators.insert(id, animator);
+        }
+    }
+    /// Check the entity makes sense, the error says what is wrong with it
+    pub fn validate(&self) -> Result<(), String> {
+        self.shape.validate()?;
+
+        if self.sprite.as_ref().is_some_and(|texture| texture.is_empty()) {
+            return Err("sprite needs a path to a PNG".into())
+        }
+        if self.animation.as_ref().is_some_and(|sheet| sheet.is_empty()) {
+            return Err("animation needs a path to a sprite sheet".into())
+        }
+        if self.sprite.is_some() && self.animation.is_some() {
+            return Err("cant have a sprite and an animation, the animation's \
+                sheet says what to draw".into())
+        }
+
+        if !self.z.is_finite() {
+            return Err(format!("z must be a finite number, it is {}", self.z))
+        }
+        if !self.rotation.is_finite() {
+            return Err(format!(
+                "rotation must be a finite number, it is {}", self.rotation
+            ))
+        }
+        let Point { x, y } = self.scale;
+        if !(x.is_finite() && y.is_finite() && x != 0.0 && y != 0.0) {
+            return Err(format!(
+                "scale {:?} must be finite and not 0.0", self.scale
+            ))
+        }
+        if self.pivot.is_some_and(|p| !(p.x.is_finite() && p.y.is_finite())) {
+            return Err("pivot must be finite".into())
+        }
+        if self.colour.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return Err(format!(
+                "colour {:?} must be between 0.0 and 1.0", self.colour
+            ))
+        }
+        let body = &self.body;
+        if !(body.mass >= 0.0 && body.drag >= 0.0) {
+            return Err("mass and drag cant be negative".into())
+        }
+        if !(body.gravity_scale.is_finite() &&
+             body.velocity.x.is_finite() && body.velocity.y.is_finite() &&
+             body.acceleration.x.is_finite() && body.acceleration.y.is_finite())
+        {
+            return Err("body values must be finite numbers".into())
+        }
+        Ok(())
+    }
+}
+
+impl ShapeDesc {
+    /// Check the shape can be built, the error says what is wrong with it
+    fn validate(&self) -> Result<(), String> {
+        let finite = |p: &Point| p.x.is_finite() && p.y.is_finite();
+        match self {
+            ShapeDesc::Polygon(points) => {
+                if points.len() < 3 {
+                    return Err(format!(
+                        "polygon needs at least 3 points, it has {}",
+                        points.len()
+                    ))
+                }
+                if !points.iter().all(finite) {
+                    return Err("polygon points must be finite".into())
+                }
+                if !Polygon::is_convex(points) {
+                    return Err("polygon must be convex with its points \
+                        going round the outside in order".into())
+                }
+            },
+            ShapeDesc::Triangle(a, b, c) => {
+                if !(finite(a) && finite(b) && finite(c)) {
+                    return Err("triangle points must be finite".into())
+                }
+                if !Polygon::is_convex(&[*a, *b, *c]) {
+                    return Err("triangle points must not be in a line".into())
+                }
+            },
+            ShapeDesc::Rectangle { min, max } => {
+                if !(finite(min) && finite(max)) {
+                    return Err("rectangle corners must be finite".into())
+                }
+                if min.x >= max.x || min.y >= max.y {
+                    return Err(format!(
+                        "rectangle min {min:?} must be below and left of \
+                        max {max:?}"
+                    ))
+                }
+            },
+            ShapeDesc::Pentagon { centre, radius } |
+            ShapeDesc::Circle { centre, radius } => {
+                if !finite(centre) {
+                    return Err("centre must be finite".into())
+                }
+                if !(radius.is_finite() && *radius > 0.0) {
+                    return Err(format!(
+                        "radius must be more than 0.0, it is {radius}"
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+    /// Build the shape this describes
+    fn to_shape(&self) -> Shape2D {
+        match self {
+            ShapeDesc::Polygon(points) => {
+                Shape2D::Polygon(Polygon::new(points.clone()))
+            },
+            ShapeDesc::Triangle(a, b, c) => {
+                Shape2D::Polygon(Polygon::triangle(*a, *b, *c))
+            },
+            ShapeDesc::Rectangle { min, max } => {
+                Shape2D::Polygon(Polygon::rectangle(
+                    Point::new(min.x, max.y), // A
+                    Point::new(max.x, max.y), // B
+                    Point::new(min.x, min.y), // C
+                    Point::new(max.x, min.y), // D
+                ))
+            },
+            ShapeDesc::Pentagon { centre, radius } => {
+                Shape2D::Polygon(Polygon::pentagon(*centre, *radius))
+            },
+            ShapeDesc::Circle { centre, radius } => {
+                Shape2D::Circle(Circle::new(*centre, *radius))
+            },
+        }
+    }
+    /// Describe a shape, we dont know if a polygon started as a triangle or
+    /// rectangle so polygons are saved as their points
+    fn from_shape(shape: &Shape2D) -> Self {
+        match shape {
+            Shape2D::Polygon(p) => ShapeDesc::Polygon(p.points.clone()),
+            Shape2D::Circle(c) => ShapeDesc::Circle {
+                centre: c.centre,
+                radius: c.radius,
+            },
+        }
+    }
+}
+
+/// Errors from serde, like an unknown field, dont know where they happened
+/// so we use how far through the `text` we got, the `remainder` is what we
+/// had not read yet
+fn parse_error(text: &str, remainder: &str, error: ron::Error) -> LevelError {
+    let (line, col) = if error.position.line == 0 {
+        let read = &text[..text.len() - remainder.len()];
+        let line = read.matches('\n').count() + 1;
+        let col = read.len() - read.rfind('\n').map_or(0, |i| i + 1) + 1;
+        (line, col)
+    } else {
+        (error.position.line, error.position.col)
+    };
+    LevelError::Parse { line, col, message: error.code.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The error from a level with one entity of `shape`, or nothing if it
+    /// is fine
+    fn shape_error(shape: &str) -> Option<String> {
+        let text = format!(
+            "(entities: [(shape: {shape}, colour: (1, 1, 1, 1))])"
+        );
+        match Level::parse(&text) {
+            Ok(_) => None,
+            Err(LevelError::Entity { index: 0, message, .. }) => Some(message),
+            Err(e) => panic!("{shape} did not parse: {e}"),
+        }
+    }
+
+    #[test]
+    fn good_shapes_are_accepted() {
+        for shape in [
+            "Polygon([(x: 0, y: 0), (x: 1, y: 0), (x: 1, y: 1), (x: 0, y: 1)])",
+            // Clockwise is fine too
+            "Polygon([(x: 0, y: 0), (x: 0, y: 1), (x: 1, y: 1), (x: 1, y: 0)])",
+            "Triangle((x: 0, y: 0), (x: 1, y: 0), (x: 0, y: 1))",
+            "Rectangle(min: (x: 0, y: 0), max: (x: 1, y: 1))",
+            "Circle(centre: (x: 0, y: 0), radius: 0.5)",
+        ] {
+            assert_eq!(shape_error(shape), None, "{shape}");
+        }
+    }
+
+    #[test]
+    fn bad_shapes_are_rejected() {
+        for shape in [
+            "Polygon([(x: 0, y: 0), (x: 1, y: 0)])",
+            // Concave
+            "Polygon([(x: 0, y: 0), (x: 2, y: 0), (x: 1, y: 0.5), \
+                (x: 2, y: 2), (x: 0, y: 2)])",
+            // A five pointed star turns the same way at every point
+            "Polygon([(x: 0, y: 1), (x: -0.59, y: -0.81), (x: 0.95, y: 0.31), \
+                (x: -0.95, y: 0.31), (x: 0.59, y: -0.81)])",
+            "Polygon([(x: 0, y: 0), (x: inf, y: 0), (x: 0, y: 1)])",
+            "Triangle((x: 0, y: 0), (x: 1, y: 1), (x: 2, y: 2))",
+            "Triangle((x: 0, y: 0), (x: NaN, y: 0), (x: 0, y: 1))",
+            "Rectangle(min: (x: 1, y: 0), max: (x: 0, y: 1))",
+            "Circle(centre: (x: 0, y: 0), radius: 0)",
+            "Pentagon(centre: (x: 0, y: inf), radius: 1)",
+        ] {
+            assert!(shape_error(shape).is_some(), "{shape} was accepted");
+        }
+    }
+
+    #[test]
+    fn parents_must_exist_and_not_loop() {
+        let level = |parents: [Option<usize>; 2]| Level {
+            entities: parents.map(|parent| EntityDesc {
+                parent,
+                ..EntityDesc::new(ShapeDesc::Circle {
+                    centre: Point::new(0.0, 0.0),
+                    radius: 1.0,
+                }, [1.0; 4])
+            }).into(),
+        };
+        assert!(level([None, Some(0)]).validate().is_ok());
+        for parents in [[Some(2), None], [Some(0), None], [Some(1), Some(0)]] {
+            assert!(matches!(
+                level(parents).validate(),
+                Err(LevelError::Entity { index: 0, .. })
+            ), "{parents:?}");
+        }
+    }
+
+    #[test]
+    fn other_bad_values_are_rejected() {
+        let circle = || EntityDesc::new(ShapeDesc::Circle {
+            centre: Point::new(0.0, 0.0),
+            radius: 1.0,
+        }, [1.0; 4]);
+        assert!(circle().validate().is_ok());
+        for desc in [
+            EntityDesc { scale: Point::new(0.0, 1.0), ..circle() },
+            EntityDesc { rotation: f32::NAN, ..circle() },
+            EntityDesc { z: f32::INFINITY, ..circle() },
+            EntityDesc { pivot: Some(Point::new(f32::NAN, 0.0)), ..circle() },
+        ] {
+            assert!(desc.validate().is_err(), "{desc:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn errors_say_where_they_are() {
+        let error = Level::parse("(entities: [(shape: Circle(centre: \
+            (x: 0, y: 0), radius: 1), colour: (1, 1, 1, 1), hat: true)])")
+            .unwrap_err();
+        assert!(matches!(error, LevelError::Parse { line: 1, .. }), "{error}");
+    }
+
+    #[test]
+    fn levels_that_cant_spawn_add_nothing() {
+        let circle = "shape: Circle(centre: (x: 0, y: 0), radius: 1), \
+            colour: (1, 1, 1, 1)";
+        let level = Level::parse(&format!("(entities: [
+            ({circle}),
+            ({circle}, animation: Some(\"assets/sprites/missing.ron\")),
+        ])")).unwrap();
+        let mut world = World::new();
+        assert!(matches!(
+            level.spawn(&mut world),
+            Err(LevelError::Entity { index: 1, .. })
+        ));
+        assert!(world.entities().is_empty());
+    }
+}
@@ -3,8 +3,17 @@
 
 pub mod entity;
 mod game;
-mod physics;
+pub mod physics;
+mod collision;
+pub mod render;
+pub mod world;
 mod controls;
-mod camera;
+pub mod camera;
+pub mod gamepad;
+pub mod headless;
+pub mod input;
+pub mod level;
+pub mod animation;
+pub mod commands;
 
 pub use game::Game;
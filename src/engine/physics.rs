@@ -1,102 +1,119 @@
 //! Here be physics
-//! 
+//!
+use serde::{Deserialize, Serialize};
 use super::Game;
-use super::entity::{Entity, Transform2D};
-use crate::globals::JUMP_SPEED;
+use super::entity::{Point, Transform};
+use crate::globals::GRAVITY;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Physics {
     pub state: State,
     pub collides: bool,
+    pub body: RigidBody,
 }
 
 impl Physics {
     pub fn new(state: State, collides: bool) -> Self {
         Self {
             state,
-            collides
+            collides,
+            body: RigidBody::default(),
         }
     }
+    /// Use `body` instead of the default [RigidBody]
+    pub fn with_body(mut self, body: RigidBody) -> Self {
+        self.body = body;
+        self
+    }
 }
 
 impl Default for Physics {
     fn default() -> Self {
-        Self { state: State::Static, collides: true }
+        Self { state: State::Static, collides: true, body: RigidBody::default() }
+    }
+}
+
+/// How an entity moves, anything that is not [State::Static] is moved by its
+/// velocity every tick
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RigidBody {
+    // Units per second
+    pub velocity: Point,
+    // Units per second per second, on top of gravity
+    pub acceleration: Point,
+    // Heavier bodies get pushed less when they hit each other
+    pub mass: f32,
+    // How much [GRAVITY] pulls on this body, 1.0 is normal gravity
+    pub gravity_scale: f32,
+    // The fraction of velocity lost every second
+    pub drag: f32,
+}
+
+impl RigidBody {
+    /// The inverse of the mass, a body with no mass cant be pushed
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass <= 0.0 { 0.0 } else { 1.0 / self.mass }
+    }
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        Self {
+            velocity: Point::new(0.0, 0.0),
+            acceleration: Point::new(0.0, 0.0),
+            mass: 1.0,
+            gravity_scale: 1.0,
+            drag: 0.0,
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum State{
+    // Standing on something
     None,
-    Jumping(u32), // Jumping with T time
+    // Moving up after a jump
+    Jumping,
+    // In the air and moving down
     Falling,
     // For terrain that has no phsyics applied
     Static,
 }
-impl State {
-    fn handle<T>(&self, shape: &mut T, dt: f32) 
-        where T: Transform2D + Entity 
-    {
-        match self {
-            State::None => {
-                shape.shift_y(-JUMP_SPEED * dt); 
-            }
-            State::Jumping(i) => {
-                match i {
-                    1.. => {  
-                        shape.shift_y(JUMP_SPEED * dt); 
-                        shape.set_state(State::Jumping(i-1)); 
-                    },
-                    0 => {
-                        shape.set_state(State::Falling);  
-                    },
-                }
-            }
-            State::Falling => {
-                shape.shift_y(-JUMP_SPEED * dt); 
-            }
-            State::Static => {}
-        }
-    }
-}
-/// Main collision logic
-fn collision(game: &mut Game) {
 
-    let player = &game.entities[0];
-    let mut new_player_y: Option<f32> = None;
-
-    // IF your next movement takes you below a collision
-    // THEN snap to that collision
-    for entity in &game.entities{
-        if  entity.collides() == true &&
-            player.y() < entity.max_y() &&
-            player.y() + (JUMP_SPEED * game.dt) >= entity.max_y() &&
-            player.max_x() > entity.x() && player.x() < entity.max_x()
-        {
-            new_player_y = Some(entity.max_y());   
-        }
+/// Move the entity with semi-implicit Euler, we update the velocity first
+/// then move by the new velocity which keeps jumps stable
+fn integrate(physics: &mut Physics, transform: &mut Transform, dt: f32) {
+    // We find out if we are still standing on something when we collide, if
+    // we walked off an edge we will be falling
+    if physics.state == State::None {
+        physics.state = State::Falling;
     }
 
-    let player = &mut game.entities[0];
-    // If we found a collision we snap the player to the edge
-    if let Some(new_y) = new_player_y{
-        player.set_y(new_y);
-        player.set_state(State::None);
-    }
+    let body = &mut physics.body;
+    let gravity = Point::new(0.0, -GRAVITY * body.gravity_scale);
+    body.velocity = body.velocity + (body.acceleration + gravity) * dt;
+    body.velocity = body.velocity * (1.0 - body.drag * dt).max(0.0);
 
-    // Hack to stop us going off the screen
-    // Need to think about this
-    if player.y() < -0.95{
-        player.set_y(-0.95);
+    transform.position = transform.position + body.velocity * dt;
+
+    // We have reached the top of our jump
+    if physics.state == State::Jumping && body.velocity.y <= 0.0 {
+        physics.state = State::Falling;
     }
 }
 
 /// Main physics loop
 pub fn update(game: &mut Game){
+    let world = &mut game.world;
 
-    for entity in &mut game.entities{
-        entity.state().handle(entity, game.dt);
+    for (id, physics, transform) in world.bodies.join_mut(&mut world.transforms) {
+        // Attached entities go where their parent puts them
+        if physics.state == State::Static || world.parents.get(id).is_some() {
+            continue
+        }
+        integrate(physics, transform, game.dt);
     }
 
-    collision(game);
+    super::collision::update(game);
 }
@@ -0,0 +1,182 @@
+//! Here we describe what the renderer needs to draw a frame, the engine
+//! builds these from the [super::world::World] so the GPU side never has to
+//! know about components
+//!
+use super::camera::Scaling;
+use super::entity::{BlendMode, Depth, Point, Shape2D, Sprite, Transform};
+use super::world::EntityId;
+use crate::globals::{VIEW_HEIGHT, VIEW_WIDTH};
+
+/// A shape and where it is in the world and how to draw it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawable {
+    // The entity this is, so the renderer can keep what it built for it
+    pub id: EntityId,
+    // Static entities never move so the renderer only builds them once
+    pub is_static: bool,
+    // In local space, the transform puts it in the world
+    pub shape: Shape2D,
+    pub transform: Transform,
+    pub depth: Depth,
+    pub blend: BlendMode,
+    // Multiplied with the sprite, or the colour of the shape without one
+    pub colour: wgpu::Color,
+    pub sprite: Option<Sprite>,
+}
+
+/// How the world is looked at this frame
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    // Takes world space to view space where the screen is 2.0 high, column
+    // major
+    pub view: [[f32; 4]; 4],
+    // How we fit the view onto a screen that is not [VIEW_WIDTH] by
+    // [VIEW_HEIGHT]
+    pub scaling: Scaling,
+}
+
+/// A [View] fitted to a screen of a given size
+#[derive(Debug, Clone, Copy)]
+pub struct Projection {
+    // Takes world space to clip space, column major
+    pub view_projection: [[f32; 4]; 4],
+    // The part of the screen we draw into in pixels, x, y, width, height
+    // from the top left
+    pub viewport: [f32; 4],
+}
+
+impl Projection {
+    /// Where a `pixel` on the screen, from the top left, is in the world.
+    /// This is None if the pixel is outside the viewport
+    pub fn screen_to_world(&self, pixel: Point) -> Option<Point> {
+        let [x, y, width, height] = self.viewport;
+        let clip = Point::new(
+            (pixel.x - x) / width * 2.0 - 1.0,
+            1.0 - (pixel.y - y) / height * 2.0,
+        );
+        if clip.x.abs() > 1.0 || clip.y.abs() > 1.0 { return None }
+
+        // Undo the 2D part of the matrix, clip = M * world + offset
+        let m = &self.view_projection;
+        let offset = clip - Point::new(m[3][0], m[3][1]);
+        let determinant = m[0][0] * m[1][1] - m[1][0] * m[0][1];
+        if determinant == 0.0 { return None }
+        Some(Point::new(
+            (m[1][1] * offset.x - m[1][0] * offset.y) / determinant,
+            (m[0][0] * offset.y - m[0][1] * offset.x) / determinant,
+        ))
+    }
+}
+
+impl View {
+    /// The view the [super::entity::Layer::Ui] layer is drawn with, fitted to
+    /// the screen the same way but without the camera
+    pub fn ui(&self) -> View {
+        View {
+            view: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            scaling: self.scaling,
+        }
+    }
+    /// Fit the view onto a `width` x `height` pixel screen. The view is
+    /// always 2.0 high, how wide it is and how much of the screen it covers
+    /// depends on [Self::scaling]
+    pub fn projection(&self, width: u32, height: u32) -> Projection {
+        let (width, height) = (width as f32, height as f32);
+        let (view_width, view_height) = (VIEW_WIDTH as f32, VIEW_HEIGHT as f32);
+        let aspect = view_width / view_height;
+
+        // How much we scale the view size by to fit it on the screen
+        let fit = (width / view_width).min(height / view_height);
+        let (x_scale, size) = match self.scaling {
+            Scaling::Stretch => (1.0 / aspect, (width, height)),
+            Scaling::Letterbox => {
+                (1.0 / aspect, (view_width * fit, view_height * fit))
+            },
+            Scaling::Expand => (height / width, (width, height)),
+            Scaling::PixelPerfect => {
+                // Whole numbers only, unless the screen is too small for that
+                let fit = if fit >= 1.0 { fit.floor() } else { fit };
+                (1.0 / aspect, (view_width * fit, view_height * fit))
+            },
+        };
+        let size = (size.0.round().min(width), size.1.round().min(height));
+
+        // Scale the rows of the view matrix, the same as multiplying by a
+        // projection that only scales
+        let mut view_projection = self.view;
+        for column in &mut view_projection {
+            column[0] *= x_scale;
+        }
+
+        Projection {
+            view_projection,
+            viewport: [
+                ((width - size.0) / 2.0).floor(),
+                ((height - size.1) / 2.0).floor(),
+                size.0,
+                size.1,
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Point, b: Point) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    /// A view looking at `centre` with the screen 2.0 high
+    fn view(centre: Point, scaling: Scaling) -> View {
+        View {
+            view: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [-centre.x, -centre.y, 0.0, 1.0],
+            ],
+            scaling,
+        }
+    }
+
+    #[test]
+    fn the_middle_of_the_screen_is_what_the_camera_looks_at() {
+        let centre = Point::new(2.0, -0.5);
+        let projection = view(centre, Scaling::Stretch)
+            .projection(VIEW_WIDTH, VIEW_HEIGHT);
+        let middle = Point::new(VIEW_WIDTH as f32, VIEW_HEIGHT as f32) * 0.5;
+        assert!(close(projection.screen_to_world(middle).unwrap(), centre));
+        // The top left corner is up and left by half the view
+        let aspect = VIEW_WIDTH as f32 / VIEW_HEIGHT as f32;
+        let corner = projection.screen_to_world(Point::new(0.0, 0.0)).unwrap();
+        assert!(close(corner, centre + Point::new(-aspect, 1.0)));
+    }
+
+    #[test]
+    fn letterbox_bars_are_not_in_the_world() {
+        // Twice as wide as the view so there are bars on the left and right
+        let projection = view(Point::new(0.0, 0.0), Scaling::Letterbox)
+            .projection(VIEW_WIDTH * 2, VIEW_HEIGHT);
+        let bar = (VIEW_WIDTH / 2) as f32;
+        let outside = Point::new(bar - 1.0, 10.0);
+        assert_eq!(projection.screen_to_world(outside), None);
+        let edge = projection.screen_to_world(Point::new(bar, 0.0)).unwrap();
+        let aspect = VIEW_WIDTH as f32 / VIEW_HEIGHT as f32;
+        assert!(close(edge, Point::new(-aspect, 1.0)));
+    }
+
+    #[test]
+    fn expand_shows_more_of_the_world() {
+        let projection = view(Point::new(0.0, 0.0), Scaling::Expand)
+            .projection(VIEW_HEIGHT * 2, VIEW_HEIGHT);
+        let left = projection.screen_to_world(Point::new(0.0, 0.0)).unwrap();
+        assert!(close(left, Point::new(-2.0, 1.0)));
+    }
+}
@@ -0,0 +1,362 @@
+//! Our entity component system, an entity is just an [EntityId] and all the
+//! data about it lives in a [Storage] per type of component. Systems like
+//! physics ask for the entities that have the components they care about
+//!
+use super::animation::Animator;
+use super::entity::{
+    BlendMode, Depth, Parent, Player, Point, Shape2D, Sprite, Transform,
+};
+use super::physics::Physics;
+
+/// A handle to an entity, the generation goes up every time an index is
+/// reused so a handle to a despawned entity never points at a new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+/// All the components of one type, stored by the index of the entity they
+/// belong to
+#[derive(Debug, Clone)]
+pub struct Storage<T> {
+    items: Vec<Option<(EntityId, T)>>,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self { items: Vec::new() }
+    }
+}
+
+impl<T> Storage<T> {
+    /// Give `id` a component, replacing any it already had
+    pub fn insert(&mut self, id: EntityId, component: T) {
+        let index = id.index as usize;
+        if index >= self.items.len() {
+            self.items.resize_with(index + 1, || None);
+        }
+        self.items[index] = Some((id, component));
+    }
+    /// Take the component away from `id`
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        let item = self.items.get_mut(id.index as usize)?;
+        match item {
+            Some((owner, _)) if *owner == id => item.take().map(|(_, c)| c),
+            _ => None,
+        }
+    }
+    /// Get the component for `id` if it has one
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        match self.items.get(id.index as usize) {
+            Some(Some((owner, component))) if *owner == id => Some(component),
+            _ => None,
+        }
+    }
+    /// Get the component for `id` to change if it has one
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        match self.items.get_mut(id.index as usize) {
+            Some(Some((owner, component))) if *owner == id => Some(component),
+            _ => None,
+        }
+    }
+    /// Every entity with this component
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        self.items.iter()
+            .filter_map(|item| item.as_ref().map(|(id, c)| (*id, c)))
+    }
+    /// Every entity that has this component and one in `other`
+    pub fn join<'a, U>(&'a self, other: &'a Storage<U>)
+        -> impl Iterator<Item = (EntityId, &'a T, &'a U)>
+    {
+        self.iter()
+            .filter_map(move |(id, t)| other.get(id).map(|u| (id, t, u)))
+    }
+    /// Every entity that has this component and one in `other`, to change
+    pub fn join_mut<'a, U>(&'a mut self, other: &'a mut Storage<U>)
+        -> impl Iterator<Item = (EntityId, &'a mut T, &'a mut U)>
+    {
+        self.items.iter_mut()
+            .zip(other.items.iter_mut())
+            .filter_map(|pair| match pair {
+                (Some((id, t)), Some((other_id, u))) if id == other_id => {
+                    Some((*id, t, u))
+                },
+                _ => None,
+            })
+    }
+}
+
+/// Our game world, this owns every entity and component
+#[derive(Debug, Default)]
+pub struct World {
+    // The current generation of each index
+    generations: Vec<u32>,
+    // Indexes of despawned entities we can reuse
+    free: Vec<u32>,
+    pub transforms: Storage<Transform>,
+    pub shapes: Storage<Shape2D>,
+    pub bodies: Storage<Physics>,
+    pub colours: Storage<wgpu::Color>,
+    pub players: Storage<Player>,
+    pub names: Storage<String>,
+    pub sprites: Storage<Sprite>,
+    pub animators: Storage<Animator>,
+    // Entities without one are at the default [Depth]
+    pub depths: Storage<Depth>,
+    // Entities without one use [BlendMode::Alpha]
+    pub blends: Storage<BlendMode>,
+    // The entities attached to another and the ones attached to each, these
+    // are kept in step by [World::set_parent]
+    pub parents: Storage<Parent>,
+    pub children: Storage<Vec<EntityId>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create an entity with no components
+    pub fn spawn(&mut self) -> EntityId {
+        match self.free.pop() {
+            Some(index) => EntityId {
+                index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                self.generations.push(0);
+                EntityId {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            },
+        }
+    }
+    /// Give `id` a shape given in world space, its position is the centre
+    /// of the shape
+    pub fn insert_shape(
+        &mut self,
+        id: EntityId,
+        shape: Shape2D,
+        colour: wgpu::Color,
+        physics: Physics,
+    ) {
+        let centre = shape.centre();
+        self.transforms.insert(id, Transform::new(centre));
+        self.shapes.insert(id, shape.translated(Point::new(0.0, 0.0) - centre));
+        self.colours.insert(id, colour);
+        self.bodies.insert(id, physics);
+    }
+    /// Remove an entity and all its components along with everything
+    /// attached to it, returns every entity removed with `id` first. This
+    /// is empty if it was already gone
+    pub fn despawn(&mut self, id: EntityId) -> Vec<EntityId> {
+        if !self.is_alive(id) { return Vec::new() }
+
+        self.detach(id);
+        let mut removed = vec![id];
+        for child in self.children.remove(id).unwrap_or_default() {
+            // The child's parent is going so there is nothing to detach from
+            self.parents.remove(child);
+            removed.extend(self.despawn(child));
+        }
+
+        self.transforms.remove(id);
+        self.shapes.remove(id);
+        self.bodies.remove(id);
+        self.colours.remove(id);
+        self.players.remove(id);
+        self.names.remove(id);
+        self.sprites.remove(id);
+        self.animators.remove(id);
+        self.depths.remove(id);
+        self.blends.remove(id);
+
+        self.generations[id.index as usize] += 1;
+        self.free.push(id.index);
+        removed
+    }
+    /// Every living entity, including ones with no components yet
+    pub fn entities(&self) -> Vec<EntityId> {
+        let mut free = vec![false; self.generations.len()];
+        for index in &self.free {
+            free[*index as usize] = true;
+        }
+        self.generations.iter()
+            .enumerate()
+            .filter(|(index, _)| !free[*index])
+            .map(|(index, generation)| EntityId {
+                index: index as u32,
+                generation: *generation,
+            })
+            .collect()
+    }
+    /// Does `id` still point at a living entity
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.generations.get(id.index as usize) == Some(&id.generation)
+    }
+    /// Attach `child` to `parent`, or to nothing if it is None. The child
+    /// stays where it is in the world and moves with its new parent from
+    /// now on. This fails if either entity is gone, the parent is scaled to
+    /// nothing or it would make the child its own ancestor
+    pub fn set_parent(&mut self, child: EntityId, parent: Option<EntityId>)
+        -> Result<(), String>
+    {
+        if !self.is_alive(child) {
+            return Err(format!("{child:?} has been despawned"))
+        }
+        let parent = match parent {
+            Some(parent) => parent,
+            None => {
+                self.detach(child);
+                return Ok(())
+            },
+        };
+        if !self.is_alive(parent) {
+            return Err(format!("{parent:?} has been despawned"))
+        }
+        if parent == child || self.is_ancestor(child, parent) {
+            return Err(format!(
+                "{child:?} cant be attached to {parent:?}, it is one of its \
+                ancestors"
+            ))
+        }
+
+        // We cant work out where the child is on a parent with no size
+        let parent_transform = self.transforms.get(parent).copied();
+        let flat = |t: Transform| t.scale.x == 0.0 || t.scale.y == 0.0;
+        if parent_transform.is_some_and(flat) {
+            return Err(format!(
+                "{child:?} cant be attached to {parent:?}, it has a scale of \
+                0.0"
+            ))
+        }
+
+        self.detach(child);
+        let world = self.transforms.get(child).copied()
+            .unwrap_or(Transform::new(Point::new(0.0, 0.0)));
+        let local = match parent_transform {
+            Some(transform) => transform.relative(&world),
+            None => world,
+        };
+        self.parents.insert(child, Parent { id: parent, local });
+        match self.children.get_mut(parent) {
+            Some(children) => children.push(child),
+            None => self.children.insert(parent, vec![child]),
+        }
+        Ok(())
+    }
+    /// Is `ancestor` the parent of `id`, or its parent's parent and so on
+    pub fn is_ancestor(&self, ancestor: EntityId, id: EntityId) -> bool {
+        let mut parent = self.parents.get(id).map(|parent| parent.id);
+        while let Some(next) = parent {
+            if next == ancestor { return true }
+            parent = self.parents.get(next).map(|parent| parent.id);
+        }
+        false
+    }
+    /// The top of the tree `id` is in, `id` itself if it has no parent
+    pub fn root(&self, id: EntityId) -> EntityId {
+        let mut root = id;
+        while let Some(parent) = self.parents.get(root) {
+            root = parent.id;
+        }
+        root
+    }
+    /// Take `id` off its parent if it has one
+    fn detach(&mut self, id: EntityId) {
+        let parent = match self.parents.remove(id) {
+            Some(parent) => parent.id,
+            None => return,
+        };
+        if let Some(siblings) = self.children.get_mut(parent) {
+            siblings.retain(|sibling| *sibling != id);
+            if siblings.is_empty() {
+                self.children.remove(parent);
+            }
+        }
+    }
+    /// Move every attached entity to where its parent puts it, parents are
+    /// done before their children so a whole tree moves together
+    pub fn propagate_transforms(&mut self) {
+        let mut stack: Vec<EntityId> = self.children.iter()
+            .map(|(id, _)| id)
+            .filter(|id| self.parents.get(*id).is_none())
+            .collect();
+        while let Some(id) = stack.pop() {
+            let parent = self.transforms.get(id).copied()
+                .unwrap_or(Transform::new(Point::new(0.0, 0.0)));
+            let children = self.children.get(id).map_or(&[][..], Vec::as_slice);
+            for &child in children {
+                if let Some(local) = self.parents.get(child).map(|p| p.local) {
+                    self.transforms.insert(child, parent.combine(&local));
+                }
+            }
+            stack.extend_from_slice(children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_handles_stay_dead_when_the_index_is_reused() {
+        let mut world = World::new();
+        let old = world.spawn();
+        world.names.insert(old, "old".into());
+        assert_eq!(world.despawn(old), vec![old]);
+        assert!(!world.is_alive(old));
+        // Despawning twice does nothing
+        assert!(world.despawn(old).is_empty());
+
+        let new = world.spawn();
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert!(world.is_alive(new) && !world.is_alive(old));
+        // The old handle can't see or touch the new entity's components
+        world.names.insert(new, "new".into());
+        assert_eq!(world.names.get(old), None);
+        assert_eq!(world.names.remove(old), None);
+        assert_eq!(world.names.get(new).map(String::as_str), Some("new"));
+    }
+
+    #[test]
+    fn join_only_visits_entities_with_both_components() {
+        let mut world = World::new();
+        let both = world.spawn();
+        let name_only = world.spawn();
+        world.names.insert(both, "both".into());
+        world.names.insert(name_only, "name".into());
+        world.depths.insert(both, Depth::default());
+        let joined: Vec<_> = world.names.join(&world.depths)
+            .map(|(id, ..)| id)
+            .collect();
+        assert_eq!(joined, vec![both]);
+    }
+
+    #[test]
+    fn parents_scaled_to_nothing_are_refused() {
+        let mut world = World::new();
+        let parent = world.spawn();
+        let child = world.spawn();
+        let mut flat = Transform::new(Point::new(0.0, 0.0));
+        flat.scale = Point::new(1.0, 0.0);
+        world.transforms.insert(parent, flat);
+        world.transforms.insert(child, Transform::new(Point::new(1.0, 1.0)));
+        assert!(world.set_parent(child, Some(parent)).is_err());
+        assert!(world.parents.get(child).is_none());
+        assert_eq!(world.children.get(parent), None);
+    }
+
+    #[test]
+    fn entities_are_the_ones_still_alive() {
+        let mut world = World::new();
+        let [a, b, c] = [world.spawn(), world.spawn(), world.spawn()];
+        world.despawn(b);
+        assert_eq!(world.entities(), [a, c]);
+        let d = world.spawn();
+        assert_eq!(world.entities(), [a, d, c]);
+    }
+}
@@ -1,6 +1,36 @@
 //! Until we know better going to keep track of our constant variables
 //! that we adjust as "Settings"
+use crate::engine::entity::Point;
+
 pub const TICK_RATE: f32 = 1.0 / 144.0;
-pub const JUMP_TICKS: u32 = 30;
+// The most ticks we will run in one frame to catch up after a slow frame
+pub const MAX_CATCHUP_TICKS: u32 = 8;
+// Units per second per second pulling everything down
+pub const GRAVITY: f32 = 7.2;
+// Upwards velocity we get when we jump
 pub const JUMP_SPEED: f32 = 3.0;
-pub const PLAYER_SPEED: f32 = 1.0;
\ No newline at end of file
+// How much of our upwards velocity we keep if jump is let go early, so
+// tapping jump does a smaller jump than holding it
+pub const JUMP_CUT: f32 = 0.5;
+// How many ticks a jump pressed in the air is remembered for, if we land in
+// that time we jump straight away
+pub const JUMP_BUFFER_TICKS: u32 = 15;
+pub const PLAYER_SPEED: f32 = 1.0;
+// How far a stick has to be pushed, from 0.0 to 1.0, before we notice
+pub const STICK_DEAD_ZONE: f32 = 0.2;
+// How far a trigger has to be pulled, from 0.0 to 1.0, before we notice
+pub const TRIGGER_DEAD_ZONE: f32 = 0.1;
+// Half the width and height of the box the player can move in before the
+// camera follows
+pub const CAMERA_DEAD_ZONE: Point = Point { x: 0.3, y: 0.2 };
+// Roughly how many seconds the camera takes to catch up with the player
+pub const CAMERA_SMOOTHING: f32 = 0.15;
+// How much one line of the mouse wheel zooms the camera in or out
+pub const ZOOM_STEP: f32 = 1.1;
+// The size of the view in pixels that the game is designed for, the view is
+// always 2.0 units high so this sets how wide it is
+pub const VIEW_WIDTH: u32 = 800;
+pub const VIEW_HEIGHT: u32 = 600;
+// The size of screenshots taken in headless mode
+pub const SCREENSHOT_WIDTH: u32 = 800;
+pub const SCREENSHOT_HEIGHT: u32 = 600;
\ No newline at end of file
@@ -1,10 +1,17 @@
 //! Here we handle the window, events and keypresses
 //! 
+use super::gamepad::DeviceGamepad;
 use super::gfx::Instance;
+use super::text::{Align, TextLayout};
 use crate::engine::Game;
+use crate::engine::entity::Point;
+use crate::engine::input::{Action, Binding, Bindings};
+use crate::engine::level::{EntityDesc, ShapeDesc};
+use crate::engine::physics::State;
+use crate::engine::world::EntityId;
 
 use winit::{
-    event::{WindowEvent, Event},
+    event::{ElementState, WindowEvent, Event, MouseButton, MouseScrollDelta},
     event_loop::{EventLoop, ControlFlow}, 
     window::{
         Window,
@@ -34,6 +41,14 @@ pub fn init_gfx(window: &Window) -> Instance {
     pollster::block_on(Instance::new(window))
         .expect("Could not init GPU/Onboard GPU")
 }
+/// Set up the GPU state without a window, drawing into a `width` x `height`
+/// texture that we can take screenshots from
+/// 
+pub fn init_offscreen_gfx(width: u32, height: u32) -> Instance {
+    // Block until we setup GPU
+    pollster::block_on(Instance::new_offscreen(width, height))
+        .expect("Could not init GPU/Software adapter")
+}
 /// We handle [Event::WindowEvent] here
 /// 
 fn handle_window_event(
@@ -54,7 +69,30 @@ fn handle_window_event(
         // Hanld when we change the size of the screen
         WindowEvent::Resized(size) => {
             gfx.resize(size.height, size.width);
-        }
+            game.resize(size.width, size.height);
+        },
+        // Track the mouse so we can click on things
+        WindowEvent::CursorMoved { position, .. } => {
+            let position = Point::new(position.x as f32, position.y as f32);
+            game.cursor_moved(Some(position));
+        },
+        WindowEvent::CursorLeft { .. } => game.cursor_moved(None),
+        WindowEvent::MouseInput { state, button, .. } => {
+            let clicked = game.mouse_button(*button, *state);
+            if *state == ElementState::Pressed {
+                if let Err(e) = demo_click(game, *button, clicked) {
+                    println!("{e}");
+                }
+            }
+        },
+        WindowEvent::MouseWheel { delta, .. } => {
+            let lines = match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                // Roughly how many pixels one line scrolls
+                MouseScrollDelta::PixelDelta(p) => p.y as f32 / 20.0,
+            };
+            game.mouse_wheel(lines);
+        },
         _ => { }
        // _ => { println!("{event:?}") }
     }
@@ -67,6 +105,7 @@ pub fn handle_events(
     event: &Event<()>,
     ctrl_flow: &mut ControlFlow,
     gfx: &mut Instance,
+    gamepad: &mut DeviceGamepad,
     game: &mut Game,
 ){
     // We send events to the appropriate handlers
@@ -83,18 +122,21 @@ pub fn handle_events(
         // Emitted when OS requests screen refresh, this is essentially our
         // main game loop
         Event::RedrawRequested(_) =>{
-            // Calculate our delta time to assist our phsyics
-            game.update_dt();
-            // This will update the game logic and call phsyics
-            game.update();
-            // This will send entities to GPU to draw
-            gfx.draw(&game.entities);
+            // Catch up on anything the pads did since the last frame
+            game.poll_gamepad(gamepad);
+            // This will run the game logic and phsyics in fixed ticks for
+            // the time since the last frame
+            game.advance();
+            draw_hud(window, gfx, game);
+            // This will send entities to GPU to draw between the last two
+            // ticks
+            gfx.draw(&game.drawables(), &game.view());
+        },
+        // Emitted when the OS sends an event to a device, this is where
+        // gamepads show up
+        Event::DeviceEvent { device_id, event } => {
+            gamepad.device_event(*device_id, event);
         },
-        // // Emitted when the OS sends an event to a device.
-        Event::DeviceEvent {
-            device_id: _,
-            event:     _,
-        } => { },
         // After we are done the rest we trigger a redraw to update the image
         // on the screen
         Event::MainEventsCleared => {
@@ -102,4 +144,109 @@ pub fn handle_events(
         },
         _ => {},
     }
+}
+
+/// Text drawn over the game, how much work the last frame was and how long
+/// each action has been held in the top left, the controls in the top right
+/// and what the mouse does along the bottom
+/// 
+fn draw_hud(window: &Window, gfx: &mut Instance, game: &Game) {
+    let mut stats = gfx.stats().to_string();
+    for action in Action::ALL {
+        let ticks = game.actions.held_ticks(action);
+        if ticks > 0 {
+            stats += &format!("\n{action:?} held for {ticks} ticks");
+        }
+    }
+    gfx.draw_text(Point::new(8.0, 8.0), 16.0, wgpu::Color::BLACK, &stats);
+
+    let width = window.inner_size().width as f32;
+    gfx.draw_text_layout(
+        Point::new(width - 8.0, 8.0),
+        16.0,
+        wgpu::Color::BLACK,
+        &controls_hint(&game.bindings),
+        TextLayout { align: Align::Right, wrap: Some(320.0) },
+    );
+
+    let height = window.inner_size().height as f32;
+    gfx.draw_text_layout(
+        Point::new(width / 2.0, height - 28.0),
+        16.0,
+        wgpu::Color::BLACK,
+        "Right click to drop or remove a box, middle click to carry one",
+        TextLayout { align: Align::Centre, wrap: None },
+    );
+}
+
+/// One line for each action saying which inputs do it, so the hint is right
+/// whatever the bindings are, the scroll wheel always zooms
+/// 
+fn controls_hint(bindings: &Bindings) -> String {
+    let mut lines: Vec<String> = Action::ALL.into_iter()
+        .filter(|action| !bindings.inputs(*action).is_empty())
+        .map(|action| {
+            let inputs: Vec<String> = bindings.inputs(action).iter()
+                .map(Binding::to_string)
+                .collect();
+            format!("{action:?}: {}", inputs.join(", "))
+        })
+        .collect();
+    lines.push("Scroll to zoom".to_string());
+    lines.join("\n")
+}
+
+/// The name of the boxes [demo_click] drops, only these can be removed or
+/// carried so the level cant be pulled apart
+const BOX: &str = "box";
+
+/// Play with the world using the mouse, left clicking says what was
+/// `clicked`, right clicking drops a box or removes the box under the cursor
+/// and middle clicking on a box makes the player carry it or puts it down
+/// 
+fn demo_click(
+    game: &mut Game,
+    button: MouseButton,
+    clicked: Option<EntityId>,
+) -> Result<(), String> {
+    let is_box = |id| game.world.names.get(id).is_some_and(|name| name == BOX);
+    match (button, clicked) {
+        (MouseButton::Left, Some(id)) => {
+            let name = game.world.names.get(id).map_or("", String::as_str);
+            println!("Clicked {id:?} {name}");
+        },
+        (MouseButton::Right, Some(id)) if is_box(id) => game.despawn(id),
+        (MouseButton::Right, None) => {
+            if let Some(cursor) = game.cursor_world() {
+                drop_box(game, cursor)?;
+            }
+        },
+        (MouseButton::Middle, Some(id)) if is_box(id) => {
+            let player = game.world.players.iter().next()
+                .map(|(player, _)| player)
+                .ok_or("There is no player to carry the box")?;
+            match game.world.parents.get(id) {
+                Some(_) => game.set_parent(id, None),
+                None => game.set_parent(id, Some(player)),
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Spawn a small box that falls from `position`
+/// 
+fn drop_box(game: &mut Game, position: Point) -> Result<EntityId, String> {
+    let half = Point::new(0.04, 0.04);
+    let shape = ShapeDesc::Rectangle {
+        min: position - half,
+        max: position + half,
+    };
+    let desc = EntityDesc {
+        name: Some(BOX.into()),
+        state: State::None,
+        ..EntityDesc::new(shape, [0.6, 0.4, 0.2, 1.0])
+    };
+    game.spawn(desc).map_err(|e| format!("Could not drop a box: {e}"))
 }
\ No newline at end of file
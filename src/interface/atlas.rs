@@ -0,0 +1,245 @@
+//! Every sprite's image is packed into a few big textures called pages when
+//! it is loaded, so sprites from different PNGs and text can be drawn
+//! together in one draw call. We pack onto shelves, rows as tall as the
+//! tallest image on them, which wastes a little space but never has to move
+//! a packed image
+//!
+use std::collections::HashMap;
+use super::gfx::Image;
+use super::texture::Texture;
+
+/// How wide and high each page is in pixels, an image bigger than this gets
+/// a page of its own
+const PAGE_SIZE: u32 = 1024;
+/// Clear pixels left around each image so sampling right at its edge cant
+/// pick up the image next to it
+const PADDING: u32 = 1;
+
+/// Where an image is in the [Atlas]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    // Which page it is on
+    pub page: usize,
+    // Left, top, right and bottom from 0.0 to 1.0 across the page
+    pub uv: [f32; 4],
+}
+
+impl AtlasRegion {
+    /// Turn `uv` from 0.0 to 1.0 across the image into where that is on the
+    /// page
+    pub fn map(&self, [u, v]: [f32; 2]) -> [f32; 2] {
+        let [left, top, right, bottom] = self.uv;
+        [left + (right - left) * u, top + (bottom - top) * v]
+    }
+}
+
+/// A row of images on a page
+struct Shelf {
+    y: u32,
+    height: u32,
+    // How much of the row is used from the left
+    used: u32,
+}
+
+/// Finds room for rectangles in a `width` x `height` area
+struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    fn new(width: u32, height: u32) -> Self {
+        Self { width, height, shelves: Vec::new() }
+    }
+    /// Find room for a `width` x `height` rectangle and return its top left,
+    /// None if it does not fit
+    fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // Use the shortest shelf it fits on so tall shelves are kept for
+        // tall images
+        let space = self.width;
+        let shelf = self.shelves.iter_mut()
+            .filter(|shelf| shelf.height >= height && space - shelf.used >= width)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = shelf {
+            let x = shelf.used;
+            shelf.used += width;
+            return Some((x, shelf.y))
+        }
+
+        // Otherwise start a new shelf under the last one
+        let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+        if width > self.width || y + height > self.height { return None }
+        self.shelves.push(Shelf { y, height, used: width });
+        Some((0, y))
+    }
+}
+
+/// One texture in the [Atlas]
+struct Page {
+    texture: Texture,
+    packer: ShelfPacker,
+}
+
+/// The textures every sprite is packed into, by the path of its PNG
+pub struct Atlas {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    pages: Vec<Page>,
+    regions: HashMap<String, AtlasRegion>,
+    // A white pixel, shapes without a sprite are drawn with this so they
+    // can share a page with sprites
+    white: AtlasRegion,
+    // The biggest texture the GPU can hold
+    max_size: u32,
+}
+
+impl Atlas {
+    /// Make an atlas with just the white pixel on its first page
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let mut atlas = Self {
+            layout: Texture::layout(device),
+            sampler: Texture::sampler(device),
+            pages: Vec::new(),
+            regions: HashMap::new(),
+            white: AtlasRegion { page: 0, uv: [0.0; 4] },
+            max_size: device.limits().max_texture_dimension_2d,
+        };
+        let region = atlas.pack(device, queue, &Image::white())
+            .expect("A pixel always fits in the atlas");
+
+        // Always sample the middle of the pixel
+        let [left, top, right, bottom] = region.uv;
+        let (u, v) = ((left + right) / 2.0, (top + bottom) / 2.0);
+        atlas.white = AtlasRegion { page: region.page, uv: [u, v, u, v] };
+        atlas
+    }
+    /// The layout of every page's bind group
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+    /// The texture of `page`
+    pub fn page(&self, page: usize) -> &Texture {
+        &self.pages[page].texture
+    }
+    /// Have we packed the image from `path` yet
+    pub fn contains(&self, path: &str) -> bool {
+        self.regions.contains_key(path)
+    }
+    /// Where the image from `path` is, plain white if there is no path or we
+    /// have not packed it
+    pub fn region(&self, path: Option<&str>) -> AtlasRegion {
+        path.and_then(|path| self.regions.get(path))
+            .copied()
+            .unwrap_or(self.white)
+    }
+    /// Pack `image` as the image from `path`
+    pub fn insert(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &str,
+        image: &Image,
+    ) -> Result<(), String> {
+        let region = self.pack(device, queue, image)?;
+        self.regions.insert(path.to_string(), region);
+        Ok(())
+    }
+    /// Draw anything from `path` plain white, used when it cant be loaded
+    pub fn insert_white(&mut self, path: &str) {
+        self.regions.insert(path.to_string(), self.white);
+    }
+    /// Find room for `image` on a page, starting a new page if none have
+    /// room, and copy it there
+    pub fn pack(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &Image,
+    ) -> Result<AtlasRegion, String> {
+        let width = image.width + PADDING * 2;
+        let height = image.height + PADDING * 2;
+
+        let packed = self.pages.iter_mut()
+            .enumerate()
+            .find_map(|(index, page)| {
+                page.packer.pack(width, height).map(|at| (index, at))
+            });
+        let (page, (x, y)) = match packed {
+            Some(packed) => packed,
+            None => {
+                if width > self.max_size || height > self.max_size {
+                    return Err(format!(
+                        "{}x{} is bigger than the GPU's biggest texture, {}",
+                        image.width, image.height, self.max_size
+                    ))
+                }
+                let size = PAGE_SIZE.min(self.max_size);
+                let (page_width, page_height) =
+                    (size.max(width), size.max(height));
+                let mut packer = ShelfPacker::new(page_width, page_height);
+                let at = packer.pack(width, height)
+                    .expect("A page is made big enough for the image");
+                self.pages.push(Page {
+                    texture: Texture::blank(
+                        device, &self.layout, &self.sampler,
+                        page_width, page_height,
+                    ),
+                    packer,
+                });
+                (self.pages.len() - 1, at)
+            },
+        };
+
+        let (x, y) = (x + PADDING, y + PADDING);
+        let packer = &self.pages[page].packer;
+        self.pages[page].texture.write(queue, x, y, image);
+
+        let (page_width, page_height) =
+            (packer.width as f32, packer.height as f32);
+        Ok(AtlasRegion {
+            page,
+            uv: [
+                x as f32 / page_width,
+                y as f32 / page_height,
+                (x + image.width) as f32 / page_width,
+                (y + image.height) as f32 / page_height,
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_fill_a_shelf_then_start_a_new_one() {
+        let mut packer = ShelfPacker::new(100, 100);
+        assert_eq!(packer.pack(60, 20), Some((0, 0)));
+        assert_eq!(packer.pack(40, 10), Some((60, 0)));
+        // No room left on the first shelf
+        assert_eq!(packer.pack(10, 10), Some((0, 20)));
+    }
+
+    #[test]
+    fn images_go_on_the_shortest_shelf_they_fit() {
+        let mut packer = ShelfPacker::new(100, 100);
+        // A tall shelf with room for something 5 wide and a short shelf
+        assert_eq!(packer.pack(95, 50), Some((0, 0)));
+        assert_eq!(packer.pack(10, 20), Some((0, 50)));
+        assert_eq!(packer.pack(5, 15), Some((10, 50)));
+        // Too tall for the short shelf
+        assert_eq!(packer.pack(5, 30), Some((95, 0)));
+    }
+
+    #[test]
+    fn images_that_dont_fit_are_refused() {
+        let mut packer = ShelfPacker::new(100, 100);
+        assert_eq!(packer.pack(101, 10), None);
+        assert_eq!(packer.pack(10, 101), None);
+        assert_eq!(packer.pack(100, 90), Some((0, 0)));
+        assert_eq!(packer.pack(10, 11), None);
+        assert_eq!(packer.pack(100, 10), Some((0, 90)));
+    }
+}
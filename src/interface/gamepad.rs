@@ -0,0 +1,237 @@
+//! Here we read gamepads from the raw [DeviceEvent]s winit gives us. Winit
+//! does not tell us what kind of device sent an event, so anything that
+//! also acts like a mouse or keyboard is ignored and the axis and button
+//! numbers are mapped to a standard pad layout by a [Mapping]
+//!
+use std::collections::{HashMap, HashSet};
+use winit::event::{DeviceEvent, DeviceId, ElementState};
+use crate::engine::gamepad::{
+    GamepadAxis, GamepadButton, GamepadEvent, GamepadSource,
+};
+
+/// How the raw axis and button numbers of a pad map onto a standard layout
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    // Raw axis number to the axis and the raw values at the two ends, a
+    // stick goes from -1.0 at the first to 1.0 at the second and a trigger
+    // goes from 0.0 to 1.0
+    axes: HashMap<u32, (GamepadAxis, f64, f64)>,
+    // Raw button number to the button
+    buttons: HashMap<u32, GamepadButton>,
+    // Raw axis and button numbers a mouse never sends, a device is only
+    // treated as a pad once it has used one of these
+    pad_only_axes: HashSet<u32>,
+    pad_only_buttons: HashSet<u32>,
+}
+
+impl Default for Mapping {
+    /// The layout of an Xbox style pad on Linux, the Y axes are flipped so
+    /// up is positive
+    fn default() -> Self {
+        use GamepadAxis::*;
+        use GamepadButton::*;
+        const STICK: f64 = 32767.0;
+        Self {
+            axes: HashMap::from([
+                (0, (LeftStickX,   -STICK, STICK)),
+                (1, (LeftStickY,   STICK, -STICK)),
+                (2, (LeftTrigger,  0.0,   255.0)),
+                (3, (RightStickX,  -STICK, STICK)),
+                (4, (RightStickY,  STICK, -STICK)),
+                (5, (RightTrigger, 0.0,   255.0)),
+            ]),
+            buttons: HashMap::from([
+                (0,  South),
+                (1,  East),
+                (2,  West),
+                (3,  North),
+                (4,  LeftShoulder),
+                (5,  RightShoulder),
+                (6,  Select),
+                (7,  Start),
+                (11, DPadUp),
+                (12, DPadDown),
+                (13, DPadLeft),
+                (14, DPadRight),
+            ]),
+            // Mice send their movement and scrolling on axes 0 to 3 and
+            // their buttons start at 1, the right stick Y, right trigger, A
+            // and the d-pad are out of their way
+            pad_only_axes: HashSet::from([4, 5]),
+            pad_only_buttons: HashSet::from([0, 11, 12, 13, 14]),
+        }
+    }
+}
+
+/// A [GamepadSource] fed by the window's [DeviceEvent]s
+///
+/// This only works where winit sends us events for pads at all, which is
+/// Linux on X11 when the X server has been set up to treat the pad as an
+/// input device. On Windows and Wayland winit only listens to mice and
+/// keyboards and on macOS it sends no device events, so pads do nothing
+/// there. The default [Mapping] is for Xbox style pads, other pads will
+/// have their buttons and axes in the wrong places. A library like gilrs
+/// would read every pad on every platform but needs libudev to build on
+/// Linux, so for now the keyboard is the only input that works everywhere.
+/// Winit cant tell us a mouse from a pad so a pad does nothing until it
+/// presses A or the d-pad or moves the right stick or trigger, what it did
+/// before that is sent then
+#[derive(Debug, Default)]
+pub struct DeviceGamepad {
+    mapping: Mapping,
+    // Events waiting for the next [GamepadSource::poll]
+    events: Vec<GamepadEvent>,
+    // Devices we have seen act like a mouse or keyboard
+    ignored: HashSet<DeviceId>,
+    // Devices that have used an input only a pad has, see [Mapping]
+    pads: HashSet<DeviceId>,
+    // What the other devices have done, held back until we know if they
+    // are pads
+    pending: HashMap<DeviceId, Vec<GamepadEvent>>,
+    // What each device has moved or held, so we can let go of it all if we
+    // find out it is not a pad
+    touched: HashMap<DeviceId, (HashSet<GamepadAxis>, HashSet<GamepadButton>)>,
+}
+
+impl DeviceGamepad {
+    /// Turn a raw event from `device` into [GamepadEvent]s, nothing is sent
+    /// until the device has used an input only a pad has
+    pub fn device_event(&mut self, device: DeviceId, event: &DeviceEvent) {
+        if self.ignored.contains(&device) { return }
+
+        let (event, pad_only) = match *event {
+            DeviceEvent::Motion { axis: raw, value } => {
+                let (axis, start, end) = match self.mapping.axes.get(&raw) {
+                    Some(mapped) => *mapped,
+                    None => return,
+                };
+                // 0.0 to 1.0 along the axis
+                let t = ((value - start) / (end - start)) as f32;
+                let value = match axis {
+                    GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => t,
+                    _ => t * 2.0 - 1.0,
+                };
+                let pad_only = self.mapping.pad_only_axes.contains(&raw);
+                (GamepadEvent::Axis { axis, value }, pad_only)
+            },
+            DeviceEvent::Button { button: raw, state } => {
+                let button = match self.mapping.buttons.get(&raw) {
+                    Some(button) => *button,
+                    None => return,
+                };
+                let pressed = state == ElementState::Pressed;
+                let pad_only = self.mapping.pad_only_buttons.contains(&raw);
+                (GamepadEvent::Button { button, pressed }, pad_only)
+            },
+            DeviceEvent::MouseMotion { .. }
+            | DeviceEvent::MouseWheel { .. }
+            | DeviceEvent::Key(_) => return self.ignore(device),
+            _ => return,
+        };
+
+        // Now we know it is a pad send what it did before we knew
+        if pad_only && self.pads.insert(device) {
+            for event in self.pending.remove(&device).unwrap_or_default() {
+                self.send(device, event);
+            }
+        }
+        if self.pads.contains(&device) {
+            self.send(device, event);
+        } else {
+            self.pending.entry(device).or_default().push(event);
+        }
+    }
+    /// Pass on an event from a pad, remembering what it moved or held
+    fn send(&mut self, device: DeviceId, event: GamepadEvent) {
+        let (axes, buttons) = self.touched.entry(device).or_default();
+        match event {
+            GamepadEvent::Axis { axis, .. } => {
+                axes.insert(axis);
+            },
+            GamepadEvent::Button { button, pressed: true } => {
+                buttons.insert(button);
+            },
+            GamepadEvent::Button { button, pressed: false } => {
+                buttons.remove(&button);
+            },
+        }
+        self.events.push(event);
+    }
+    /// Stop listening to `device` and let go of anything it was holding
+    fn ignore(&mut self, device: DeviceId) {
+        self.ignored.insert(device);
+        self.pending.remove(&device);
+        let (axes, buttons) = match self.touched.remove(&device) {
+            Some(touched) => touched,
+            None => return,
+        };
+        for axis in axes {
+            self.events.push(GamepadEvent::Axis { axis, value: 0.0 });
+        }
+        for button in buttons {
+            self.events.push(GamepadEvent::Button { button, pressed: false });
+        }
+    }
+}
+
+impl GamepadSource for DeviceGamepad {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> DeviceId {
+        // Safe as we only compare it with itself
+        unsafe { DeviceId::dummy() }
+    }
+
+    fn motion(axis: u32, value: f64) -> DeviceEvent {
+        DeviceEvent::Motion { axis, value }
+    }
+
+    fn button(button: u32, state: ElementState) -> DeviceEvent {
+        DeviceEvent::Button { button, state }
+    }
+
+    #[test]
+    fn mice_never_move_the_stick() {
+        let mut gamepad = DeviceGamepad::default();
+        // The raw events a mouse sends before it says it is a mouse
+        for event in [
+            motion(0, 12.0),
+            motion(1, -3.0),
+            button(1, ElementState::Pressed),
+            button(1, ElementState::Released),
+            DeviceEvent::MouseMotion { delta: (12.0, -3.0) },
+            motion(0, 5.0),
+        ] {
+            gamepad.device_event(device(), &event);
+        }
+        assert!(gamepad.poll().is_empty());
+    }
+
+    #[test]
+    fn pads_send_what_they_did_once_we_know_they_are_pads() {
+        let mut gamepad = DeviceGamepad::default();
+        gamepad.device_event(device(), &motion(0, 32767.0));
+        assert!(gamepad.poll().is_empty());
+
+        // Pressing A tells us it is a pad
+        gamepad.device_event(device(), &button(0, ElementState::Pressed));
+        assert_eq!(gamepad.poll(), [
+            GamepadEvent::Axis { axis: GamepadAxis::LeftStickX, value: 1.0 },
+            GamepadEvent::Button {
+                button: GamepadButton::South,
+                pressed: true,
+            },
+        ]);
+        gamepad.device_event(device(), &motion(1, 32767.0));
+        assert_eq!(gamepad.poll(), [
+            GamepadEvent::Axis { axis: GamepadAxis::LeftStickY, value: -1.0 },
+        ]);
+    }
+}
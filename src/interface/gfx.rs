@@ -1,23 +1,38 @@
 //! Here we deal with all things Grpahics using [wgpu], we use [pollster] to 
 //! handle the async parts 
 
-use crate::engine::entity::Shape2D;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::engine::entity::{BlendMode, Depth, Layer, Point};
+use crate::engine::render::{Drawable, View};
+use crate::engine::world::EntityId;
+use super::atlas::Atlas;
+use super::text::{Font, GlyphQuad, Text, TextLayout};
 
-/// This struct repesents a position in 2d space, we use these in batches of 3
-/// to build [crate::entity::Shape2D]
+/// How many bytes each vertex and index buffer holds before it first has to
+/// grow
+const INITIAL_BUFFER_SIZE: usize = 8192;
+/// The font text is drawn with, built in so we can draw text wherever we are
+/// run from
+pub const FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+
+/// This struct repesents a corner in 2d space, indices say which three make
+/// each triangle of a [crate::engine::entity::Shape2D]
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Zeroable, bytemuck::Pod)]
 struct Vertex2D{
     coord: [f32; 2], // x, y
-    colour: [f32; 4], // x, y
+    colour: [f32; 4], // r, g, b, a
+    uv: [f32; 2], // where in the texture, from the top left
 }
 
 impl Vertex2D{
     /// Create new [Vertex2D]
-    fn new(x: f32, y: f32, colour: wgpu::Color) -> Self{
+    fn new(x: f32, y: f32, colour: wgpu::Color, uv: [f32; 2]) -> Self{
         Self{
             coord: [x, y],
             colour: [colour.r as f32, colour.g as f32, colour.b as f32, colour.a as f32],
+            uv,
         }
     }
     /// This descriptor is passed to [wgpu::RenderPipelineDescriptor]
@@ -36,20 +51,319 @@ impl Vertex2D{
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32;6]>() as u64,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
 }
 
+/// When a batch is drawn, batches are drawn from the back to the front
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Order {
+    depth:       Depth,
+    // See through, drawn after the opaque batches at the same depth so what
+    // is behind it is already there to blend with
+    translucent: bool,
+}
+
+impl Order {
+    /// Which of two orders is drawn first
+    fn order(&self, other: &Order) -> std::cmp::Ordering {
+        self.depth.order(&other.depth)
+            .then(self.translucent.cmp(&other.translucent))
+    }
+}
+
+/// A run of indices that are drawn with the same atlas page and blend mode
+/// at the same depth, each batch is one draw call
+struct Batch {
+    page:    usize,
+    blend:   BlendMode,
+    order:   Order,
+    indices: Range<u32>,
+}
+
+/// How much work a frame took, so we can see how well drawables are being
+/// batched
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub draw_calls: usize,
+    pub vertices:   usize,
+    pub indices:    usize,
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f, "{} draw calls, {} vertices, {} indices",
+            self.draw_calls, self.vertices, self.indices
+        )
+    }
+}
+
+/// A buffer on the GPU that grows to fit what we write to it
+struct GpuBuffer {
+    buffer:   wgpu::Buffer,
+    usage:    wgpu::BufferUsages,
+    // How many bytes fit in [Self::buffer]
+    capacity: usize,
+    // What we last wrote, so we only write when it changes
+    contents: Vec<u8>,
+}
+
+impl GpuBuffer {
+    fn new(device: &wgpu::Device, usage: wgpu::BufferUsages) -> Self {
+        Self {
+            buffer: Self::create(device, usage, INITIAL_BUFFER_SIZE),
+            usage,
+            capacity: INITIAL_BUFFER_SIZE,
+            contents: Vec::new(),
+        }
+    }
+    fn create(device: &wgpu::Device, usage: wgpu::BufferUsages, size: usize)
+        -> wgpu::Buffer
+    {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size as u64,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    /// Write `bytes` to the start of the buffer if they are not there already
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8]) {
+        // Nothing has changed so whats on the GPU is still correct
+        if bytes == self.contents { return }
+
+        // Reallocate with room to spare so we dont do this every time an
+        // entity is added
+        if bytes.len() > self.capacity {
+            self.capacity = bytes.len().next_power_of_two();
+            self.buffer = Self::create(device, self.usage, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytes);
+        self.contents = bytes.to_vec();
+    }
+}
+
+/// Vertices and indices built up on the CPU split into [Batch]es by page
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: Vec<Vertex2D>,
+    indices:  Vec<u32>,
+    batches:  Vec<Batch>,
+}
+
+impl MeshBuilder {
+    /// Add a mesh drawn from `page` with `blend`, its `indices` count from
+    /// its first vertex. It joins the last batch if that is drawn the same
+    /// way at the same time
+    fn push(
+        &mut self,
+        page: usize,
+        blend: BlendMode,
+        order: Order,
+        vertices: &[Vertex2D],
+        indices: &[u32],
+    ) {
+        let base = self.vertices.len() as u32;
+        let start = self.indices.len() as u32;
+        self.vertices.extend_from_slice(vertices);
+        self.indices.extend(indices.iter().map(|index| base + index));
+        let end = self.indices.len() as u32;
+
+        match self.batches.last_mut() {
+            Some(batch) if batch.page == page
+                && batch.blend == blend
+                && batch.order == order =>
+            {
+                batch.indices.end = end;
+            },
+            _ => self.batches.push(Batch {
+                page, blend, order, indices: start..end,
+            }),
+        }
+    }
+}
+
+/// Vertices and indices on the GPU and how they are split into draw calls
+struct Geometry {
+    vertices: GpuBuffer,
+    indices:  GpuBuffer,
+    batches:  Vec<Batch>,
+}
+
+impl Geometry {
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            vertices: GpuBuffer::new(device, wgpu::BufferUsages::VERTEX),
+            indices: GpuBuffer::new(device, wgpu::BufferUsages::INDEX),
+            batches: Vec::new(),
+        }
+    }
+    /// Write what `builder` built to the GPU and take its batches
+    fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        builder: MeshBuilder,
+    ) {
+        self.vertices.write(device, queue, bytemuck::cast_slice(&builder.vertices));
+        self.indices.write(device, queue, bytemuck::cast_slice(&builder.indices));
+        self.batches = builder.batches;
+    }
+    /// Draw `batches` from this geometry, the camera must already be set and
+    /// be the right one for the layer the batches are in
+    fn draw<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a HashMap<BlendMode, wgpu::RenderPipeline>,
+        atlas: &'a Atlas,
+        batches: &[Batch],
+    ) {
+        if batches.is_empty() { return }
+        rpass.set_vertex_buffer(0, self.vertices.buffer.slice(..));
+        rpass.set_index_buffer(
+            self.indices.buffer.slice(..), wgpu::IndexFormat::Uint32
+        );
+        let mut blend = None;
+        for batch in batches {
+            // Only switch pipelines when the blend mode changes
+            if blend != Some(batch.blend) {
+                rpass.set_pipeline(&pipelines[&batch.blend]);
+                blend = Some(batch.blend);
+            }
+            let page = atlas.page(batch.page);
+            rpass.set_bind_group(1, &page.bind_group, &[]);
+            rpass.draw_indexed(batch.indices.clone(), 0, 0..1);
+        }
+    }
+    fn vertex_count(&self) -> usize {
+        self.vertices.contents.len() / std::mem::size_of::<Vertex2D>()
+    }
+    fn index_count(&self) -> usize {
+        self.indices.contents.len() / std::mem::size_of::<u32>()
+    }
+}
+
+/// What we built for a static entity, kept until the entity changes
+struct StaticMesh {
+    // What we built it from, if the drawable is not the same we rebuild it
+    drawable: Drawable,
+    page:     usize,
+    order:    Order,
+    vertices: Vec<Vertex2D>,
+    indices:  Vec<u32>,
+}
+
+/// Where we draw our frames to
+enum Target {
+    /// A window surface that we present frames to
+    Surface {
+        surface: wgpu::Surface,
+        config:  wgpu::SurfaceConfiguration,
+    },
+    /// A texture that we read frames back from, used for screenshots when we
+    /// dont have a window
+    Offscreen {
+        texture: wgpu::Texture,
+        width:   u32,
+        height:  u32,
+    },
+}
+
+/// A frame read back from the GPU as RGBA8 pixels, row by row from the top left
+pub struct Image {
+    pub width:  u32,
+    pub height: u32,
+    pub rgba:   Vec<u8>,
+}
+
+impl Image {
+    /// Read a PNG from `path`, any PNG colour type is turned into RGBA8
+    pub fn load_png(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+        // Turn palettes and small bit depths into 8 bits per channel
+        decoder.set_transformations(
+            png::Transformations::EXPAND | png::Transformations::STRIP_16
+        );
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+        pixels.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => pixels,
+            png::ColorType::Rgb => pixels.chunks(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels.chunks(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => pixels.iter()
+                .flat_map(|p| [*p, *p, *p, 255])
+                .collect(),
+            png::ColorType::Indexed => {
+                return Err("palette was not expanded".into())
+            },
+        };
+        Ok(Self { width: info.width, height: info.height, rgba })
+    }
+    /// A single white pixel, drawing with it leaves the colour unchanged
+    pub fn white() -> Self {
+        Self { width: 1, height: 1, rgba: vec![255; 4] }
+    }
+    /// Write the image to `path` as a PNG
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(
+            std::io::BufWriter::new(file), self.width, self.height
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.rgba))
+            .map_err(|e| e.to_string())
+    }
+}
+
 /// This struct handles the basic state of our GPU after we initialise it, we 
 /// can use these to then request models and shaders to be rendered
 pub struct Instance {
-    surface:         wgpu::Surface,
-    surface_config:  wgpu::SurfaceConfiguration,
+    target:          Target,
     device:          wgpu::Device,
     queue:           wgpu::Queue,
-    buffer:          wgpu::Buffer,
-    render_pipeline: wgpu::RenderPipeline,
+    // Entities that never move, this is only rebuilt when one changes
+    static_geometry: Geometry,
+    static_meshes:   HashMap<EntityId, StaticMesh>,
+    // Everything else and the text, built every frame. The text batches come
+    // after the world's and are drawn over it
+    dynamic:         Geometry,
+    text_batches:    Vec<Batch>,
+    // What the last frame took to draw
+    stats:           FrameStats,
+    // Holds the camera's view projection matrix for the vertex shader
+    camera_buffer:   wgpu::Buffer,
+    camera_bind:     wgpu::BindGroup,
+    // Holds the view projection matrix without the camera, for the UI layer
+    ui_buffer:       wgpu::Buffer,
+    ui_bind:         wgpu::BindGroup,
+    // Holds a matrix from pixels to clip space for drawing text
+    screen_buffer:   wgpu::Buffer,
+    screen_bind:     wgpu::BindGroup,
+    // Every sprite's image and glyph packed into a few textures
+    atlas:           Atlas,
+    // The built in [FONT] unless [Self::load_font] gave us another
+    font:            Font,
+    // Text to draw in the next frame
+    texts:           Vec<Text>,
+    // One for each way of blending, they only differ in their blend state
+    pipelines:       HashMap<BlendMode, wgpu::RenderPipeline>,
 }
 
 impl Instance{
@@ -73,40 +387,16 @@ impl Instance{
         };
         
         // Get the adapter with the specified options
-        let adapter = instance.request_adapter(&adaptor_options).await;
-        
-        // Throw error if dont have an adapter, give us GPU and Backend chosen
-        // Return a device and queue from our adapter
-        let (device, queue) = match &adapter {
-            Some(adapter) => {
-                // Get basic adapter info
-                let info = adapter.get_info();
-                println!("GPU: {}, Backend: {:?}", &info.name, &info.backend);
-                
-                // Set up our device, the label is for debugging, we use the 
-                // adapter to determine what the features to enable are and what 
-                // are the best limits
-                let device_desc = wgpu::DeviceDescriptor {
-                    label:    Some("My Device Descriptor"),
-                    limits:   adapter.limits(),
-                    features: adapter.features(),
-                };
-                
-                // Request the adapter with our device descriptor
-                adapter.request_device(&device_desc, None)
-                .await
-                .expect("Could not get device")
-            }
-            // Not implemented handling if we do not get a single adapter yet
-            None => todo!("No adaptor found")
-        };
+        let adapter = instance.request_adapter(&adaptor_options).await
+            .ok_or(())?;
+        let (device, queue) = Self::request_device(&adapter).await;
         
         let window_size = window.inner_size();
         // Surface Config
         let surface_config = wgpu::SurfaceConfiguration {
             usage:  wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface.get_preferred_format(
-                &adapter.unwrap()
+                &adapter
             ).expect("No texture format supported"),
             width: window_size.width,
             height: window_size.height,
@@ -116,15 +406,83 @@ impl Instance{
         // Initize surface for presentation
         surface.configure(&device, &surface_config);
 
-        let buffer = device.create_buffer(
-            &wgpu::BufferDescriptor {
-                label: None,
-                size: 1024,
-                usage: wgpu::BufferUsages::all(),
-                mapped_at_creation: false,
+        let format = surface_config.format;
+        let target = Target::Surface { surface, config: surface_config };
+        Ok(Self::with_target(target, format, device, queue))
+    }
+
+    /// Init our WGPU api without a window, we draw into a `width` x `height`
+    /// texture that can be read back with [Self::screenshot]. We prefer the
+    /// fallback (software) adapter so this works on machines without a GPU
+    pub async fn new_offscreen(width: u32, height: u32) -> Result<Self,()> {
+        // We take any backend here as a software GL driver is fine
+        let instance: wgpu::Instance 
+            = wgpu::Instance::new(wgpu::Backends::all());
+
+        let mut adaptor_options = wgpu::RequestAdapterOptions {
+            power_preference:       wgpu::PowerPreference::LowPower,
+            compatible_surface:     None,
+            force_fallback_adapter: true,
+        };
+        // If there is no software adapter we will take whatever there is
+        let adapter = match instance.request_adapter(&adaptor_options).await {
+            Some(adapter) => adapter,
+            None => {
+                adaptor_options.force_fallback_adapter = false;
+                instance.request_adapter(&adaptor_options).await.ok_or(())?
             }
-        );
+        };
+        let (device, queue) = Self::request_device(&adapter).await;
+
+        // We use RGBA so we can read the pixels straight back into an [Image]
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+
+        let target = Target::Offscreen { texture, width, height };
+        Ok(Self::with_target(target, format, device, queue))
+    }
+
+    /// Get a device and queue from our `adapter`, this also prints the GPU we
+    /// ended up with
+    async fn request_device(adapter: &wgpu::Adapter) 
+        -> (wgpu::Device, wgpu::Queue) 
+    {
+        // Get basic adapter info
+        let info = adapter.get_info();
+        println!("GPU: {}, Backend: {:?}", &info.name, &info.backend);
+        
+        // Set up our device, the label is for debugging, we use the 
+        // adapter to determine what the features to enable are and what 
+        // are the best limits
+        let device_desc = wgpu::DeviceDescriptor {
+            label:    Some("My Device Descriptor"),
+            limits:   adapter.limits(),
+            features: adapter.features(),
+        };
         
+        // Request the adapter with our device descriptor
+        adapter.request_device(&device_desc, None)
+        .await
+        .expect("Could not get device")
+    }
+
+    /// Set up the buffers and pipeline that draw into `target`, which holds
+    /// textures of `format`
+    fn with_target(
+        target: Target, 
+        format: wgpu::TextureFormat,
+        device: wgpu::Device, 
+        queue: wgpu::Queue,
+    ) -> Self {
         // Initialize my shaders
         let shader_desc = wgpu::ShaderModuleDescriptor {
             label: None,
@@ -134,21 +492,65 @@ impl Instance{
         };
         let shader = device.create_shader_module(&shader_desc);
 
-        //// Dont think we need this yet
-        // let pipeline_layout_desc = wgpu::PipelineLayoutDescriptor {
-        //     label:                Some(&pipeline_layout_label),
-        //     bind_group_layouts:   &[],
-        //     push_constant_ranges: &[],
-        // };
-        // let pipeline_layout 
-        //     = self.device.create_pipeline_layout(&pipeline_layout_desc);
-
-        // Init render pipeline
-        let render_pipeline = device.create_render_pipeline(
+        // The camera matrix lives in a uniform the vertex shader reads from
+        let camera_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            }
+        );
+        let matrix = |label| {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: std::mem::size_of::<[[f32; 4]; 4]>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: &camera_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            (buffer, bind)
+        };
+        let (camera_buffer, camera_bind) = matrix("Camera");
+        let (ui_buffer, ui_bind) = matrix("UI");
+        // Text is placed in pixels so it stays the same size at any zoom
+        let (screen_buffer, screen_bind) = matrix("Screen");
+
+        let static_geometry = Geometry::new(&device);
+        let dynamic = Geometry::new(&device);
+
+        // Every draw has a texture, plain shapes use a white pixel in it
+        let atlas = Atlas::new(&device, &queue);
+        let font = Font::from_bytes(FONT).expect("Built in font is not valid");
+
+        let pipeline_layout = device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label:                Some("Pipeline Layout"),
+                bind_group_layouts:   &[&camera_layout, atlas.layout()],
+                push_constant_ranges: &[],
+            }
+        );
+
+        // Init a render pipeline for each blend mode
+        let pipeline = |blend| device.create_render_pipeline(
             &wgpu::RenderPipelineDescriptor {
                     label: None,
-                    //layout: Some(&pipeline_layout),
-                    layout: None,
+                    layout: Some(&pipeline_layout),
                     // This is for shape
                     vertex: wgpu::VertexState { 
                         module: &shader, 
@@ -159,10 +561,11 @@ impl Instance{
                     fragment: Some(wgpu::FragmentState {
                         module: &shader,
                         entry_point: "fs_main",
-                        targets: &[
-                            wgpu::ColorTargetState::from(
-                                surface_config.format)
-                        ]
+                        targets: &[wgpu::ColorTargetState {
+                            format,
+                            blend: Some(blend_state(blend)),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        }]
                     }),
                     primitive: wgpu::PrimitiveState::default(),
                     multisample: wgpu::MultisampleState::default(),
@@ -170,15 +573,38 @@ impl Instance{
                     multiview: None,
                 }
             );
+        let pipelines = BlendMode::ALL.into_iter()
+            .map(|blend| (blend, pipeline(blend)))
+            .collect();
 
-        Ok(Self {
-            surface,
-            surface_config,
+        Self {
+            target,
             device,
             queue,
-            buffer,
-            render_pipeline,
-        })
+            static_geometry,
+            static_meshes: HashMap::new(),
+            dynamic,
+            text_batches: Vec::new(),
+            stats: FrameStats::default(),
+            camera_buffer,
+            camera_bind,
+            ui_buffer,
+            ui_bind,
+            screen_buffer,
+            screen_bind,
+            atlas,
+            font,
+            texts: Vec::new(),
+            pipelines,
+        }
+    }
+
+    /// The width and height in pixels of what we draw into
+    fn size(&self) -> (u32, u32) {
+        match &self.target {
+            Target::Surface { config, .. } => (config.width, config.height),
+            Target::Offscreen { width, height, .. } => (*width, *height),
+        }
     }
 
     /// This resizing the window when the user adjusts the window size
@@ -186,40 +612,266 @@ impl Instance{
         // if area == 0 it will panic
         if height * width == 0 { return }
 
-        self.surface_config.height = height;
-        self.surface_config.width = width;
-        self.surface.configure(&self.device, &self.surface_config);
+        // Offscreen targets are a fixed size
+        if let Target::Surface { surface, config } = &mut self.target {
+            config.height = height;
+            config.width = width;
+            surface.configure(&self.device, config);
+        }
     }
 
-    /// Main entry point for user to create a shape
-    pub fn draw(&self, entities: &[Shape2D]) {
+    /// What the last frame took to draw
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
 
-        // Puts all the entities into the vertex buffer
-        let entity_buffer = self.create_buffer(entities);
-        
-        // This puts our entities into the GPU command queue to be sent
-        // to the GPU when ready
+    /// Draw text with the font at `path` instead, if it cant be loaded we
+    /// keep the font we have and say why
+    pub fn load_font(&mut self, path: &str) -> Result<(), String> {
+        self.font = Font::load(path)?;
+        Ok(())
+    }
+
+    /// Draw `text` in the next frame with its first line's top left at
+    /// `position` pixels from the top left, `size` is the line height in
+    /// pixels
+    pub fn draw_text(
+        &mut self,
+        position: Point,
+        size: f32,
+        colour: wgpu::Color,
+        text: &str,
+    ) {
+        self.draw_text_layout(position, size, colour, text, TextLayout::default());
+    }
+
+    /// Like [Self::draw_text] but wrapped and aligned by `layout`, with an
+    /// alignment other than left `position` is where each line ends or has
+    /// its middle
+    pub fn draw_text_layout(
+        &mut self,
+        position: Point,
+        size: f32,
+        colour: wgpu::Color,
+        text: &str,
+        layout: TextLayout,
+    ) {
+        self.texts.push(Text {
+            position,
+            size,
+            colour,
+            text: text.to_string(),
+            layout,
+        });
+    }
+
+    /// Main entry point for user to draw shapes, the engine gives us them
+    /// already placed in the world and the `view` to look at them with. Any
+    /// text from [Self::draw_text] is drawn over them
+    pub fn draw(&mut self, drawables: &[Drawable], view: &View) {
+        self.load_textures(drawables);
+        let glyphs = self.layout_text();
+        self.upload(drawables, &glyphs);
+
+        // Fit the view to the size we are drawing at
+        let (width, height) = self.size();
+        let projection = view.projection(width, height);
         self.queue.write_buffer(
-            &self.buffer,
+            &self.camera_buffer,
             0,
-            bytemuck::cast_slice(&entity_buffer)
+            bytemuck::cast_slice(&projection.view_projection),
         );
-        
-        // Get the next frame from the surface
-        let frame = match self.surface.get_current_texture() {
-            Ok(frame) => { frame },
-            // No idea why this panics so lets just handle it and not draw
-            Err(wgpu::SurfaceError::Outdated) => { return }, 
-            // I want to panic if anything else happens, not expected
-            Err(e) => {
-                panic!("{e:?}")
+        self.queue.write_buffer(
+            &self.ui_buffer,
+            0,
+            bytemuck::cast_slice(&view.ui().projection(width, height).view_projection),
+        );
+        // Pixels from the top left to clip space, y goes down in pixels but
+        // up in clip space
+        let (width, height) = (width as f32, height as f32);
+        let screen: [[f32; 4]; 4] = [
+            [2.0 / width, 0.0,           0.0, 0.0],
+            [0.0,         -2.0 / height, 0.0, 0.0],
+            [0.0,         0.0,           1.0, 0.0],
+            [-1.0,        1.0,           0.0, 1.0],
+        ];
+        self.queue.write_buffer(
+            &self.screen_buffer, 0, bytemuck::cast_slice(&screen)
+        );
+
+        match &self.target {
+            Target::Surface { surface, .. } => {
+                // Get the next frame from the surface
+                let frame = match surface.get_current_texture() {
+                    Ok(frame) => { frame },
+                    // No idea why this panics so lets just handle it and not draw
+                    Err(wgpu::SurfaceError::Outdated) => { return }, 
+                    // I want to panic if anything else happens, not expected
+                    Err(e) => {
+                        panic!("{e:?}")
+                    },
+                };
+
+                let texture_view = frame.texture.create_view(
+                    &wgpu::TextureViewDescriptor::default()
+                );
+                self.render(&texture_view, projection.viewport);
+
+                // Show the output on the surface
+                frame.present();
             },
+            Target::Offscreen { texture, .. } => {
+                let texture_view = texture.create_view(
+                    &wgpu::TextureViewDescriptor::default()
+                );
+                self.render(&texture_view, projection.viewport);
+            },
+        }
+    }
+
+    /// Draw the entities into our offscreen target and read the pixels back,
+    /// this fails if we were created with a window
+    pub fn screenshot(&mut self, drawables: &[Drawable], view: &View) 
+        -> Result<Image, ()> 
+    {
+        if let Target::Surface { .. } = self.target { return Err(()) }
+        self.draw(drawables, view);
+
+        let (texture, width, height) = match &self.target {
+            Target::Offscreen { texture, width, height } => {
+                (texture, *width, *height)
+            },
+            Target::Surface { .. } => unreachable!(),
         };
 
-        let view = frame.texture.create_view(
-            &wgpu::TextureViewDescriptor::default()
+        // Rows we copy out of a texture must be padded to a multiple of
+        // [wgpu::COPY_BYTES_PER_ROW_ALIGNMENT]
+        let row_bytes = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.div_ceil(align) * align;
+
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Readback"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor { 
+            label: None 
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
         );
+        self.queue.submit(Some(encoder.finish()));
+
+        // Wait for the GPU to finish then copy the rows without the padding
+        let slice = readback.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).map_err(|_| ())?;
+
+        let padded = slice.get_mapped_range();
+        let mut rgba = Vec::with_capacity((row_bytes * height) as usize);
+        for row in padded.chunks(padded_row_bytes as usize) {
+            rgba.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        drop(padded);
+        readback.unmap();
+
+        Ok(Image { width, height, rgba })
+    }
+
+    /// Turn the entities and glyphs into vertices and indices and write them
+    /// to the GPU if they have changed since the last frame
+    fn upload(&mut self, drawables: &[Drawable], glyphs: &[(GlyphQuad, wgpu::Color)]) {
+        let (statics, dynamics): (Vec<&Drawable>, Vec<&Drawable>) = drawables
+            .iter()
+            .partition(|drawable| drawable.is_static);
 
+        if self.update_static_meshes(&statics) {
+            let mut meshes: Vec<(&EntityId, &StaticMesh)> =
+                self.static_meshes.iter().collect();
+            // By depth then in the order the entities were made, the map
+            // has no order of its own
+            meshes.sort_by(|(a_id, a), (b_id, b)| {
+                a.order.order(&b.order).then(a_id.cmp(b_id))
+            });
+            let mut builder = MeshBuilder::default();
+            for (_, mesh) in meshes {
+                builder.push(
+                    mesh.page,
+                    mesh.drawable.blend,
+                    mesh.order,
+                    &mesh.vertices,
+                    &mesh.indices,
+                );
+            }
+            self.static_geometry.upload(&self.device, &self.queue, builder);
+        }
+
+        let mut builder = self.create_buffer(&dynamics);
+        let world_batches = std::mem::take(&mut builder.batches);
+        Self::create_text_buffer(glyphs, &mut builder);
+        self.text_batches = std::mem::replace(&mut builder.batches, world_batches);
+        self.dynamic.upload(&self.device, &self.queue, builder);
+
+        let (statics, dynamic) = (&self.static_geometry, &self.dynamic);
+        self.stats = FrameStats {
+            draw_calls: statics.batches.len() + dynamic.batches.len()
+                + self.text_batches.len(),
+            vertices: statics.vertex_count() + dynamic.vertex_count(),
+            indices: statics.index_count() + dynamic.index_count(),
+        };
+    }
+
+    /// Build any static entities that are new or have changed and forget
+    /// ones that are gone, true if anything changed
+    fn update_static_meshes(&mut self, statics: &[&Drawable]) -> bool {
+        let before = self.static_meshes.len();
+        let ids: HashSet<EntityId> = statics.iter()
+            .map(|drawable| drawable.id)
+            .collect();
+        self.static_meshes.retain(|id, _| ids.contains(id));
+        let mut changed = self.static_meshes.len() != before;
+
+        for drawable in statics {
+            let built = self.static_meshes.get(&drawable.id)
+                .is_some_and(|mesh| mesh.drawable == **drawable);
+            if built { continue }
+
+            let (page, vertices, indices) = self.mesh(drawable);
+            self.static_meshes.insert(drawable.id, StaticMesh {
+                drawable: (*drawable).clone(),
+                page,
+                order: order(drawable),
+                vertices,
+                indices,
+            });
+            changed = true;
+        }
+        changed
+    }
+
+    /// Record and submit the commands to draw our uploaded vertices into 
+    /// the `viewport` of `view`, anything outside it is left as the
+    /// background colour
+    fn render(&self, view: &wgpu::TextureView, viewport: [f32; 4]) {
         // Init the Command Encoder
         let mut encoder = self.device.create_command_encoder(
         &wgpu::CommandEncoderDescriptor { 
@@ -230,7 +882,7 @@ impl Instance{
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: None,
             color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
                     // BackgroundColour
@@ -240,17 +892,47 @@ impl Instance{
             }],
             depth_stencil_attachment: None,
         });
-        // use our pipeline we init in our constructer
-        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, &self.camera_bind, &[]);
+        let [x, y, width, height] = viewport;
+        rpass.set_viewport(x, y, width, height, 0.0, 1.0);
 
-        // Put the vertex buffer into slot 0 of the GPU
-        rpass.set_vertex_buffer(
-            0, 
-            self.buffer.slice(..),
-        );
+        // Have to call this last after setting everything for the render_pass,
+        // we draw each run of indices with its atlas page from the back to
+        // the front. Static and moving entities are each sorted by depth so
+        // we take whichever is further back next, at the same depth static
+        // entities go behind and translucent ones go in front of opaque ones
+        let (pipelines, atlas) = (&self.pipelines, &self.atlas);
+        let mut statics = self.static_geometry.batches.as_slice();
+        let mut dynamics = self.dynamic.batches.as_slice();
+        let mut ui = false;
+        while let Some(next) = next_order(statics, dynamics) {
+            if next.depth.layer == Layer::Ui && !ui {
+                rpass.set_bind_group(0, &self.ui_bind, &[]);
+                ui = true;
+            }
+            let count = statics.iter()
+                .take_while(|batch| batch.order.order(&next).is_le())
+                .count();
+            let (now, later) = statics.split_at(count);
+            self.static_geometry.draw(&mut rpass, pipelines, atlas, now);
+            statics = later;
+
+            let count = dynamics.iter()
+                .take_while(|batch| batch.order.order(&next).is_le())
+                .count();
+            let (now, later) = dynamics.split_at(count);
+            self.dynamic.draw(&mut rpass, pipelines, atlas, now);
+            dynamics = later;
+        }
 
-        // Have to call this last after setting everything for the render_pass
-        rpass.draw(0..(entity_buffer.len() as u32), 0..1);
+        // Text goes over everything and can be anywhere on the screen, even
+        // in the bars around a letterboxed view
+        if !self.text_batches.is_empty() {
+            let (width, height) = self.size();
+            rpass.set_bind_group(0, &self.screen_bind, &[]);
+            rpass.set_viewport(0.0, 0.0, width as f32, height as f32, 0.0, 1.0);
+        }
+        self.dynamic.draw(&mut rpass, pipelines, atlas, &self.text_batches);
 
         // We need to drop this as it owns encoder which we need to use in the 
         // nextline
@@ -258,51 +940,175 @@ impl Instance{
 
         // Send to the GPU
         self.queue.submit(Some(encoder.finish()));
-        // Show the output on the surface
-        frame.present();
-    }
-
-    /// Turn a shape into a buffer of its triangles we accept 
-    /// [crate::entity::Shape] then turn it into a triangle or
-    /// Rectangle on the GPU, we match on the shape and then create the
-    /// shape from the coordinates
-    fn create_buffer(&self, entities: &[Shape2D]) -> Vec<Vertex2D> {
-        // Create an empty vec
-        let mut vertex_buf: Vec<Vertex2D> = Vec::new();
-        // Go through all entities we are given by engine and add them to 
-        // buffer
-        for entity in entities{
-            match entity {
-                // Turn the entity into 2D Vertexs
-                Shape2D::Triangle(t) => {
-                    vertex_buf.push(Vertex2D::new(t.a.x, t.a.y, t.colour)); //A
-                    vertex_buf.push(Vertex2D::new(t.b.x, t.b.y, t.colour)); //B
-                    vertex_buf.push(Vertex2D::new(t.c.x, t.c.y, t.colour)); //C
-                },
-                Shape2D::Rectangle(r) => {
-                    vertex_buf.push(Vertex2D::new(r.a.x, r.a.y, r.colour)); //A
-                    vertex_buf.push(Vertex2D::new(r.b.x, r.b.y, r.colour)); //B
-                    vertex_buf.push(Vertex2D::new(r.c.x, r.c.y, r.colour)); //C
-                    
-                    vertex_buf.push(Vertex2D::new(r.b.x, r.b.y, r.colour)); //B
-                    vertex_buf.push(Vertex2D::new(r.c.x, r.c.y, r.colour)); //C
-                    vertex_buf.push(Vertex2D::new(r.d.x, r.d.y, r.colour)); //D
-                },
-                &Shape2D::Pentagon(p) => {
-                    vertex_buf.push(Vertex2D::new(p.a.x, p.a.y, p.colour)); //A
-                    vertex_buf.push(Vertex2D::new(p.b.x, p.b.y, p.colour)); //B
-                    vertex_buf.push(Vertex2D::new(p.c.x, p.c.y, p.colour)); //C
-
-                    vertex_buf.push(Vertex2D::new(p.b.x, p.b.y, p.colour)); //B
-                    vertex_buf.push(Vertex2D::new(p.c.x, p.c.y, p.colour)); //C
-                    vertex_buf.push(Vertex2D::new(p.d.x, p.d.y, p.colour)); //D
-
-                    vertex_buf.push(Vertex2D::new(p.c.x, p.c.y, p.colour)); //C
-                    vertex_buf.push(Vertex2D::new(p.d.x, p.d.y, p.colour)); //D
-                    vertex_buf.push(Vertex2D::new(p.e.x, p.e.y, p.colour)); //E
-                }
+    }
+
+    /// Pack any sprites' images we have not loaded yet into the atlas, if
+    /// one cant be loaded we say why once and draw it white
+    fn load_textures(&mut self, drawables: &[Drawable]) {
+        for sprite in drawables.iter().filter_map(|d| d.sprite.as_ref()) {
+            let path = &sprite.texture;
+            if self.atlas.contains(path) { continue }
+
+            let packed = Image::load_png(path).and_then(|image| {
+                self.atlas.insert(&self.device, &self.queue, path, &image)
+            });
+            if let Err(e) = packed {
+                eprintln!("Could not load texture {path}: {e}");
+                self.atlas.insert_white(path);
             }
         }
-        vertex_buf
+    }
+
+    /// Lay out the text waiting to be drawn into the glyphs to draw with
+    /// their colour, this packs any glyphs we have not drawn before
+    fn layout_text(&mut self) -> Vec<(GlyphQuad, wgpu::Color)> {
+        let texts = std::mem::take(&mut self.texts);
+        let font = &mut self.font;
+        texts.iter()
+            .flat_map(|text| {
+                font.layout(&self.device, &self.queue, &mut self.atlas, text)
+                    .into_iter()
+                    .map(|glyph| (glyph, text.colour))
+            })
+            .collect()
+    }
+
+    /// Add a quad for each glyph to `builder`, glyphs dont overlap so we can
+    /// sort them by page
+    fn create_text_buffer(
+        glyphs: &[(GlyphQuad, wgpu::Color)],
+        builder: &mut MeshBuilder,
+    ) {
+        let mut sorted: Vec<_> = glyphs.iter().collect();
+        sorted.sort_by_key(|(glyph, _)| glyph.region.page);
+
+        // Text is always drawn after everything so its order does not matter
+        let order = Order {
+            depth: Depth { layer: Layer::Ui, z: f32::INFINITY },
+            translucent: false,
+        };
+        for (glyph, colour) in sorted {
+            let (min, max) = (glyph.min, glyph.max);
+            let corner = |x: f32, y: f32, u: f32, v: f32| {
+                Vertex2D::new(x, y, *colour, glyph.region.map([u, v]))
+            };
+            builder.push(
+                glyph.region.page,
+                BlendMode::Alpha,
+                order,
+                &[
+                    corner(min.x, min.y, 0.0, 0.0),
+                    corner(max.x, min.y, 1.0, 0.0),
+                    corner(min.x, max.y, 0.0, 1.0),
+                    corner(max.x, max.y, 1.0, 1.0),
+                ],
+                &[0, 2, 1, 1, 2, 3],
+            );
+        }
+    }
+
+    /// Build the drawables into a [MeshBuilder] sorted by depth, with
+    /// translucent drawables after the opaque ones at their depth. The sort
+    /// is stable so drawables at the same depth are drawn in the order they
+    /// were made, the later on top, and only neighbours on the same page
+    /// share a draw call
+    fn create_buffer(&self, drawables: &[&Drawable]) -> MeshBuilder {
+        let mut meshes: Vec<_> = drawables.iter()
+            .map(|drawable| (order(drawable), drawable.blend, self.mesh(drawable)))
+            .collect();
+        meshes.sort_by(|(a, _, _), (b, _, _)| a.order(b));
+
+        let mut builder = MeshBuilder::default();
+        for (order, blend, (page, vertices, indices)) in meshes {
+            builder.push(page, blend, order, &vertices, &indices);
+        }
+        builder
+    }
+
+    /// Turn a drawable into vertices and indices, we ask its shape for the
+    /// [crate::engine::entity::Mesh] that makes it up and each point becomes
+    /// a vertex in the drawable's colour. A sprite is stretched over the box
+    /// around the shape in its local space so it turns and stretches with
+    /// the shape, we also return the atlas page it is drawn from
+    fn mesh(&self, drawable: &Drawable) -> (usize, Vec<Vertex2D>, Vec<u32>) {
+        let path = drawable.sprite.as_ref()
+            .map(|sprite| sprite.texture.as_str());
+        let region = self.atlas.region(path);
+        let [left, top, right, bottom] = drawable.sprite.as_ref()
+            .map_or([0.0; 4], |sprite| sprite.region);
+        let aabb = drawable.shape.aabb();
+        let size = aabb.max - aabb.min;
+
+        let mesh = drawable.shape.mesh();
+        let vertices = mesh.points.iter()
+            .map(|point| {
+                // How far across and down the box the point is
+                let across = (point.x - aabb.min.x) / size.x;
+                let down = (aabb.max.y - point.y) / size.y;
+                let uv = region.map([
+                    left + (right - left) * across,
+                    top + (bottom - top) * down,
+                ]);
+                let world = drawable.shape
+                    .point_to_world(*point, &drawable.transform);
+                Vertex2D::new(world.x, world.y, drawable.colour, uv)
+            })
+            .collect();
+        (region.page, vertices, mesh.indices)
+    }
+}
+
+/// How the colour from the fragment shader, which has its alpha
+/// premultiplied, is mixed with what is already drawn. We never change the
+/// alpha of what is drawn except when blending alpha, so screenshots of
+/// additive and multiplied shapes stay opaque
+fn blend_state(blend: BlendMode) -> wgpu::BlendState {
+    let keep = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation:  wgpu::BlendOperation::Add,
+    };
+    match blend {
+        // Sprites' clear parts show what is behind them
+        BlendMode::Alpha => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        // What is behind plus our colour, already scaled by our alpha
+        BlendMode::Additive => wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation:  wgpu::BlendOperation::Add,
+            },
+            alpha: keep,
+        },
+        // What is behind times our colour, faded to what is behind by our
+        // alpha: dst * (src * a) + dst * (1 - a)
+        BlendMode::Multiply => wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Dst,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation:  wgpu::BlendOperation::Add,
+            },
+            alpha: keep,
+        },
+    }
+}
+
+/// When `drawable` is drawn, anything not drawn with plain alpha blending
+/// can show what is behind it so it counts as translucent
+fn order(drawable: &Drawable) -> Order {
+    Order {
+        depth: drawable.depth,
+        translucent: drawable.blend != BlendMode::Alpha
+            || drawable.colour.a < 1.0,
+    }
+}
+
+/// The order of whichever of the next static or moving batches is further
+/// back, None when both have been drawn
+fn next_order(statics: &[Batch], dynamics: &[Batch]) -> Option<Order> {
+    match (statics.first(), dynamics.first()) {
+        (Some(a), Some(b)) if b.order.order(&a.order).is_lt() => Some(b.order),
+        (Some(a), _) => Some(a.order),
+        (None, b) => b.map(|b| b.order),
     }
 }
\ No newline at end of file
@@ -4,5 +4,9 @@
 //! 
 pub mod gfx;
 pub mod app;
+pub mod gamepad;
+pub mod texture;
+pub mod atlas;
+pub mod text;
 
-pub use app::{init_gfx, init_window, handle_events};
\ No newline at end of file
+pub use app::{init_gfx, init_offscreen_gfx, init_window, handle_events};
\ No newline at end of file
@@ -2,26 +2,45 @@
 struct VertexIn {
     [[location(0)]] position: vec2<f32>;
     [[location(1)]] colour: vec4<f32>;
+    [[location(2)]] uv: vec2<f32>;
 };
 
+// The camera's view projection matrix, this takes world space to clip space
+struct Camera {
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+// The texture we draw from, shapes without a sprite get a white one
+[[group(1), binding(0)]]
+var sprite_texture: texture_2d<f32>;
+[[group(1), binding(1)]]
+var sprite_sampler: sampler;
+
 // We store the location here and pass the colour to the fragment shader
 // This comes from vs_main()
 struct VertexOut{
     [[location(0)]] colour: vec4<f32>;
+    [[location(1)]] uv: vec2<f32>;
     [[builtin(position)]] position: vec4<f32>;
 };
 
-// This draws vertexes, every 3 make a triangle
+// This draws vertexes, every 3 indices make a triangle
 [[stage(vertex)]]
 fn vs_main(v: VertexIn) -> VertexOut {
     var out: VertexOut;
-    out.position = vec4<f32>(v.position[0], v.position[1], 0.0, 1.0);
+    out.position = camera.view_proj * vec4<f32>(v.position, 0.0, 1.0);
     out.colour = vec4<f32>(v.colour);
+    out.uv = v.uv;
     return out;
 }
 
-// Colours in sets of 3 vertices
+// Colours in sets of 3 vertices, the texture is tinted by the colour. We
+// give back premultiplied alpha, the colour already scaled by how opaque it
+// is, which every blend mode expects
 [[stage(fragment)]]
 fn fs_main(v: VertexOut) -> [[location(0)]] vec4<f32> {
-    return vec4<f32>(v.colour); // This is the colour of the shape
+    let colour = v.colour * textureSample(sprite_texture, sprite_sampler, v.uv);
+    return vec4<f32>(colour.rgb * colour.a, colour.a);
 }
\ No newline at end of file
@@ -0,0 +1,257 @@
+//! Text is drawn from TTF or OTF fonts. Each glyph is rasterised the first
+//! time it is drawn at a size and packed into the [Atlas], so text is batched
+//! with sprites. Positions and sizes are in pixels from the top left of what
+//! we draw into
+//!
+use std::collections::HashMap;
+use ab_glyph::{Font as _, FontArc, GlyphId, PxScale, ScaleFont};
+use crate::engine::entity::Point;
+use super::atlas::{Atlas, AtlasRegion};
+use super::gfx::Image;
+
+/// Which part of each line goes at the position text is drawn at
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Centre,
+    Right,
+}
+
+/// How to lay out text
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TextLayout {
+    pub align: Align,
+    // Lines wider than this many pixels are broken between words
+    pub wrap: Option<f32>,
+}
+
+/// Text waiting to be drawn in the next frame
+#[derive(Debug, Clone)]
+pub struct Text {
+    // The top of the first line, where it goes across depends on the align
+    pub position: Point,
+    // How tall a line is in pixels
+    pub size: f32,
+    pub colour: wgpu::Color,
+    pub text: String,
+    pub layout: TextLayout,
+}
+
+/// One glyph to draw, `min` and `max` are its top left and bottom right
+pub struct GlyphQuad {
+    pub region: AtlasRegion,
+    pub min: Point,
+    pub max: Point,
+}
+
+/// A glyph we have rasterised, its box is from where it sits on the line
+struct CachedGlyph {
+    region: AtlasRegion,
+    min: Point,
+    max: Point,
+}
+
+/// A font and the glyphs we have packed from it
+pub struct Font {
+    font: FontArc,
+    // By glyph and size, None for glyphs like space that draw nothing
+    glyphs: HashMap<(GlyphId, u32), Option<CachedGlyph>>,
+}
+
+impl Font {
+    /// Read a TTF or OTF font from `path`
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        let font = FontArc::try_from_vec(bytes).map_err(|e| e.to_string())?;
+        Ok(Self { font, glyphs: HashMap::new() })
+    }
+    /// Read a TTF or OTF font from `bytes`, such as a font built into the
+    /// game with `include_bytes!`
+    pub fn from_bytes(bytes: &'static [u8]) -> Result<Self, String> {
+        let font = FontArc::try_from_slice(bytes).map_err(|e| e.to_string())?;
+        Ok(Self { font, glyphs: HashMap::new() })
+    }
+    /// Lay out `text` into the glyphs to draw, any glyphs we have not drawn
+    /// at this size before are packed into `atlas`
+    pub fn layout(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        atlas: &mut Atlas,
+        text: &Text,
+    ) -> Vec<GlyphQuad> {
+        let scale = PxScale::from(text.size);
+        // Cloning only copies a pointer, we need our own so we can add to
+        // the cache while we read the font
+        let font = self.font.clone();
+        let scaled = font.as_scaled(scale);
+        let line_height = scaled.height() + scaled.line_gap();
+
+        let mut quads = Vec::new();
+        for (index, line) in self.lines(scale, text).iter().enumerate() {
+            let width = self.width(scale, line);
+            let x = match text.layout.align {
+                Align::Left => 0.0,
+                Align::Centre => -width / 2.0,
+                Align::Right => -width,
+            };
+            // Glyphs sit on the baseline, which is the ascent below the top
+            let mut caret = text.position + Point::new(
+                x, scaled.ascent() + index as f32 * line_height
+            );
+            let mut previous = None;
+            for c in line.chars() {
+                let id = font.glyph_id(c);
+                if let Some(previous) = previous {
+                    caret.x += scaled.kern(previous, id);
+                }
+                // Start glyphs on whole pixels so they stay sharp
+                let origin = Point::new(caret.x.round(), caret.y.round());
+                if let Some(glyph) = self.glyph(device, queue, atlas, id, scale) {
+                    quads.push(GlyphQuad {
+                        region: glyph.region,
+                        min: origin + glyph.min,
+                        max: origin + glyph.max,
+                    });
+                }
+                caret.x += scaled.h_advance(id);
+                previous = Some(id);
+            }
+        }
+        quads
+    }
+    /// Split `text` into lines at new lines and, if it wraps, between words
+    /// that would go past the wrap width. A word wider than that gets a line
+    /// to itself
+    fn lines(&self, scale: PxScale, text: &Text) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.text.lines() {
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let longer = match line.is_empty() {
+                    true => word.to_string(),
+                    false => format!("{line} {word}"),
+                };
+                match text.layout.wrap {
+                    Some(wrap) if !line.is_empty()
+                        && self.width(scale, &longer) > wrap =>
+                    {
+                        lines.push(std::mem::replace(&mut line, word.to_string()));
+                    },
+                    _ => line = longer,
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+    /// How wide `line` is in pixels
+    fn width(&self, scale: PxScale, line: &str) -> f32 {
+        let scaled = self.font.as_scaled(scale);
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                width += scaled.kern(previous, id);
+            }
+            width += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    }
+    /// Get a glyph from the cache, rasterising it if it is not there
+    fn glyph(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        atlas: &mut Atlas,
+        id: GlyphId,
+        scale: PxScale,
+    ) -> Option<&CachedGlyph> {
+        let key = (id, scale.y.to_bits());
+        if !self.glyphs.contains_key(&key) {
+            let glyph = self.rasterise(device, queue, atlas, id, scale);
+            self.glyphs.insert(key, glyph);
+        }
+        self.glyphs[&key].as_ref()
+    }
+    /// Draw a glyph in white with how much of each pixel it covers as the
+    /// alpha, so it takes on the colour of the text, and pack it
+    fn rasterise(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        atlas: &mut Atlas,
+        id: GlyphId,
+        scale: PxScale,
+    ) -> Option<CachedGlyph> {
+        let outline = self.font.outline_glyph(id.with_scale(scale))?;
+        let bounds = outline.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        if width == 0 || height == 0 { return None }
+
+        let mut rgba = [255, 255, 255, 0].repeat((width * height) as usize);
+        outline.draw(|x, y, coverage| {
+            let alpha = (y * width + x) as usize * 4 + 3;
+            rgba[alpha] = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+        });
+
+        let image = Image { width, height, rgba };
+        let region = atlas.pack(device, queue, &image)
+            .map_err(|e| eprintln!("Could not pack glyph {id:?}: {e}"))
+            .ok()?;
+        Some(CachedGlyph {
+            region,
+            min: Point::new(bounds.min.x, bounds.min.y),
+            max: Point::new(bounds.max.x, bounds.max.y),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::gfx::FONT;
+
+    fn text(text: &str, wrap: Option<f32>) -> Text {
+        Text {
+            position: Point::new(0.0, 0.0),
+            size: 16.0,
+            colour: wgpu::Color::BLACK,
+            text: text.to_string(),
+            layout: TextLayout { align: Align::Left, wrap },
+        }
+    }
+
+    fn lines(text: &Text) -> Vec<String> {
+        let font = Font::from_bytes(FONT).unwrap();
+        font.lines(PxScale::from(text.size), text)
+    }
+
+    #[test]
+    fn fonts_load_from_files() {
+        assert!(Font::load("assets/fonts/DejaVuSans.ttf").is_ok());
+        assert!(Font::load("assets/fonts/missing.ttf").is_err());
+        // Not a font
+        assert!(Font::load("Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn lines_are_broken_at_new_lines() {
+        assert_eq!(lines(&text("one two\nthree", None)), ["one two", "three"]);
+    }
+
+    #[test]
+    fn long_lines_wrap_between_words() {
+        let font = Font::from_bytes(FONT).unwrap();
+        let wrap = font.width(PxScale::from(16.0), "one two");
+        assert_eq!(
+            lines(&text("one two three four", Some(wrap))),
+            ["one two", "three", "four"]
+        );
+        // A word too wide to fit still gets drawn
+        assert_eq!(lines(&text("one two", Some(1.0))), ["one", "two"]);
+    }
+}
@@ -1,5 +1,29 @@
 //! Program entry point, sets up everything then runs a main game loop
-//! 
+//!
+//! Passing `--headless <ticks>` runs the game without a window or GPU for
+//! that many ticks, optionally with `--script <file>` to script the inputs,
+//! see [engine::headless::parse_script] for the format. Adding
+//! `--screenshot <file>` renders the final state to a PNG with an offscreen
+//! (software if there is no GPU) renderer
+//!
+//! Passing `--level <file>` loads that level instead of the default one, see
+//! [engine::level] for the format. In headless mode `--save-level <file>`
+//! saves the final state as a level
+//!
+//! Passing `--scaling <stretch|letterbox|expand|pixel-perfect>` picks how the
+//! view is fitted to a window that is not the shape the game was designed for
+//!
+//! Passing `--bindings <file>` loads which keys do what, see
+//! [engine::input::Bindings::parse] for the format, and `--save-bindings
+//! <file>` writes the bindings we are using so they can be edited
+//!
+//! Passing `--font <file>` draws text with that TTF or OTF font instead of
+//! the one built in, if it cant be loaded we say why and use the built in
+//! one
+//!
+//! Gamepads are only read on Linux with X11 and are expected to be laid out
+//! like an Xbox pad, see [interface::gamepad::DeviceGamepad] for why. Use
+//! the keyboard everywhere else
 
 mod interface;
 mod engine;
@@ -7,14 +31,40 @@ mod globals;
 
 /// This function handles the main game loop with the multiple components
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Set up our Game engine
+    let mut game = setup_game(&args);
+
+    // Run without a window if asked to
+    if let Some(ticks) = arg_value(&args, "--headless") {
+        let ticks = ticks.parse().expect("--headless expects a number of ticks");
+        return headless(
+            ticks,
+            game,
+            arg_value(&args, "--script"),
+            arg_value(&args, "--screenshot"),
+            arg_value(&args, "--save-level"),
+            arg_value(&args, "--font"),
+        );
+    }
+
     // Setup the window, our window lives as long as _window lives
     let (event_loop, window) = interface::init_window();
 
     // Set up our GPU or onboard graphics
     let mut gfx_instance = interface::init_gfx(&window);
+    if let Some(path) = arg_value(&args, "--font") {
+        load_font(&mut gfx_instance, path);
+    }
+
+    // The window might not be the size the game starts out expecting
+    let size = window.inner_size();
+    game.resize(size.width, size.height);
+
+    // Gamepads come to us as raw device events
+    let mut gamepad = interface::gamepad::DeviceGamepad::default();
 
-    // Set up our Game engine
-    let mut game = engine::Game::new();
     println!("{:?}", game);
 
     // Listens for events in the windows and we handle our responses to those
@@ -23,10 +73,104 @@ fn main() {
         // Handle events
         interface::handle_events(
             &window,
-            &event, 
+            &event,
             ctrl_flow,
-            &mut gfx_instance, 
+            &mut gfx_instance,
+            &mut gamepad,
             &mut game
         );
     })
 }
+
+/// Step the game `ticks` times at [globals::TICK_RATE] with the inputs from
+/// `script` then print the final state so it can be compared between runs,
+/// if we are given a `screenshot` path we also render the state to it and if
+/// we are given a `save` path we save the state as a level. Text in the
+/// screenshot is drawn with `font` if we are given one
+fn headless(
+    ticks: u32,
+    game: engine::Game,
+    script: Option<&str>,
+    screenshot: Option<&str>,
+    save: Option<&str>,
+    font: Option<&str>,
+) {
+    let mut runner = engine::headless::Headless::new(game, globals::TICK_RATE);
+
+    if let Some(path) = script {
+        let script = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read script {path}: {e}"));
+        let inputs = engine::headless::parse_script(&script)
+            .unwrap_or_else(|e| panic!("Bad script {path}: {e}"));
+        runner.script(&inputs);
+    }
+
+    runner.run(ticks);
+    // One line per entity so runs are easy to compare
+    let world = &runner.game().world;
+    for (id, transform) in world.transforms.iter() {
+        let physics = world.bodies.get(id);
+        println!("{id:?} {:?} {:?}", transform.position, physics);
+    }
+    println!("Camera {:?}", runner.game().camera.position);
+
+    if let Some(path) = screenshot {
+        let mut gfx = interface::init_offscreen_gfx(
+            globals::SCREENSHOT_WIDTH,
+            globals::SCREENSHOT_HEIGHT,
+        );
+        if let Some(path) = font {
+            load_font(&mut gfx, path);
+        }
+        gfx.screenshot(&runner.game().drawables(), &runner.game().view())
+            .expect("Could not read back the screenshot")
+            .save_png(path)
+            .unwrap_or_else(|e| panic!("Could not save screenshot {path}: {e}"));
+        println!("Frame {}", gfx.stats());
+    }
+
+    if let Some(path) = save {
+        runner.game()
+            .save_level(path)
+            .unwrap_or_else(|e| panic!("Could not save level {path}: {e}"));
+    }
+}
+
+/// Create the game with the level, scaling and bindings asked for in `args`,
+/// we cant run with bad settings so we stop and say what is wrong with them
+fn setup_game(args: &[String]) -> engine::Game {
+    let mut game = engine::Game::new();
+    if let Some(path) = arg_value(args, "--level") {
+        game.load_level(path)
+            .unwrap_or_else(|e| panic!("Bad level {path}: {e}"));
+    }
+    if let Some(scaling) = arg_value(args, "--scaling") {
+        game.camera.scaling = scaling.parse()
+            .unwrap_or_else(|e| panic!("Bad --scaling: {e}"));
+    }
+    if let Some(path) = arg_value(args, "--bindings") {
+        game.bindings = engine::input::Bindings::load(path)
+            .unwrap_or_else(|e| panic!("Bad bindings {path}: {e}"));
+    }
+    if let Some(path) = arg_value(args, "--save-bindings") {
+        game.bindings.save(path)
+            .unwrap_or_else(|e| panic!("Could not save bindings {path}: {e}"));
+    }
+    game
+}
+
+/// Draw text with the font at `path`, text still works with the built in
+/// font so we only say what went wrong
+fn load_font(gfx: &mut interface::gfx::Instance, path: &str) {
+    if let Err(e) = gfx.load_font(path) {
+        eprintln!("Could not load font {path}, using the built in one: {e}");
+    }
+}
+
+/// Find the value following `flag` in the command line arguments
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}